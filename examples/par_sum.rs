@@ -6,7 +6,7 @@ fn par_sum(v: &[i32]) -> i32 {
     if v.len() <= THRESHOLD {
         v.iter().copied().sum()
     } else {
-        let half = (v.len() + 1) / 2;
+        let half = v.len().div_ceil(2);
         let sums = Parallel::new().each(v.chunks(half), par_sum).run();
         sums.into_iter().sum()
     }
@@ -0,0 +1,30 @@
+use easy_parallel::Parallel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 4;
+
+// The first few items are much more expensive than the rest
+fn cost(i: u64) -> Duration {
+    Duration::from_millis(if i < 8 { 20 } else { 1 })
+}
+
+fn work(i: u64) -> u64 {
+    thread::sleep(cost(i));
+    i
+}
+
+fn main() {
+    // Static chunks: the first chunk gets every expensive item
+    let start = Instant::now();
+    let chunks = Parallel::new().each_chunked(THREADS, 0..64, work).run();
+    let chunked = start.elapsed();
+
+    // Shared queue: free threads keep taking the next item
+    let start = Instant::now();
+    let queued = Parallel::new().max_threads(THREADS).each(0..64, work).run();
+    let dynamic = start.elapsed();
+
+    assert_eq!(chunks.concat(), queued);
+    println!("chunked: {:?}, queued: {:?}", chunked, dynamic);
+}
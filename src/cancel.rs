@@ -0,0 +1,110 @@
+//! Cooperative cancellation of the closures in a batch.
+
+use std::cell::RefCell;
+
+use crate::sync::{AtomicBool, Arc, Ordering};
+
+#[cfg(not(loom))]
+thread_local! {
+    /// The flag of the batch whose closure is running on this thread
+    static CURRENT: RefCell<Option<Cancel>> = const { RefCell::new(None) };
+}
+
+// loom threads share one OS thread, so they need loom's thread locals
+#[cfg(loom)]
+loom::thread_local! {
+    static CURRENT: RefCell<Option<Cancel>> = RefCell::new(None);
+}
+
+/// Returns whether the batch running the current closure has been asked to stop.
+///
+/// Terminators such as [`Parallel::race`](crate::Parallel::race) stop a batch
+/// once they have their answer. Closures that haven't started by then are
+/// skipped, but running closures are never interrupted: long running ones can
+/// poll this and return early. Outside of a closure it returns `false`.
+///
+/// ```
+/// use easy_parallel::{is_cancelled, Parallel};
+///
+/// let first = Parallel::new()
+///     .add(|| {
+///         while !is_cancelled() {
+///             std::thread::yield_now();
+///         }
+///         "gave up"
+///     })
+///     .add(|| "done")
+///     .race();
+/// assert_eq!(first, Some("done"));
+/// ```
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|current| current.borrow().as_ref().is_some_and(Cancel::is_cancelled))
+}
+
+/// A stop flag shared by the closures of one run
+#[derive(Clone, Default)]
+pub(crate) struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Runs `f` with this as the flag seen by [`is_cancelled`].
+    ///
+    /// `f` must not unwind.
+    pub(crate) fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let prev = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let r = f();
+        CURRENT.with(|current| *current.borrow_mut() = prev);
+        r
+    }
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use super::{is_cancelled, Cancel};
+    use loom::thread;
+
+    #[test]
+    fn cancel_reaches_entered_closures() {
+        loom::model(|| {
+            let cancel = Cancel::default();
+            let worker = {
+                let cancel = cancel.clone();
+                thread::spawn(move || {
+                    let seen = cancel.enter(is_cancelled);
+                    (seen, is_cancelled())
+                })
+            };
+            cancel.cancel();
+            let (_, after) = worker.join().unwrap();
+            // The flag is only seen inside `enter`
+            assert!(!after);
+            assert!(cancel.enter(is_cancelled));
+            assert!(!is_cancelled());
+        });
+    }
+
+    #[test]
+    fn nested_enter_restores_the_outer_flag() {
+        loom::model(|| {
+            let (outer, inner) = (Cancel::default(), Cancel::default());
+            let worker = {
+                let (outer, inner) = (outer.clone(), inner.clone());
+                thread::spawn(move || outer.enter(|| inner.enter(is_cancelled)))
+            };
+            outer.cancel();
+            // The inner flag hides the outer one until its closure returns
+            assert!(!worker.join().unwrap());
+            assert!(outer.enter(|| {
+                assert!(!inner.enter(is_cancelled));
+                is_cancelled()
+            }));
+        });
+    }
+}
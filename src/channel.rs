@@ -0,0 +1,11 @@
+//! Channels carrying results back to a batch, which are crossbeam's with the
+//! `crossbeam-channel` feature.
+//!
+//! Both kinds are used through the same subset of methods: `send`, `recv`,
+//! `recv_timeout` and `iter`.
+
+#[cfg(feature = "crossbeam-channel")]
+pub(crate) use crossbeam_channel::{unbounded as channel, Receiver, Sender};
+
+#[cfg(not(feature = "crossbeam-channel"))]
+pub(crate) use std::sync::mpsc::{channel, Receiver, Sender};
@@ -0,0 +1,88 @@
+//! Awaiting a batch from async code.
+
+use std::fmt::Formatter;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::PanicPolicy;
+
+/// The future returned by [`Parallel::run_async`](crate::Parallel::run_async).
+///
+/// Resolves to the results of the closures, in the order they were added. If a
+/// closure panicked, the panic is resumed when the future is polled.
+#[must_use = "futures do nothing unless polled, but the closures still run"]
+pub struct RunFuture<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    policy: PanicPolicy,
+}
+
+struct Shared<T> {
+    /// The output of the batch, or the panic that stopped it
+    results: Option<std::thread::Result<Vec<std::thread::Result<T>>>>,
+    waker: Option<Waker>,
+}
+
+impl<T> RunFuture<T> {
+    /// Runs `f` on a new thread, or right away where there are none,
+    /// completing the future with its output, or with its panic.
+    pub(crate) fn spawn<F>(policy: PanicPolicy, f: F) -> RunFuture<T>
+    where
+        F: FnOnce() -> Vec<std::thread::Result<T>> + Send + 'static,
+        T: Send + 'static
+    {
+        let shared = Arc::new(Mutex::new(Shared {
+            results: None,
+            waker: None,
+        }));
+        let s = shared.clone();
+        let run = move || {
+            let results = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            let waker = {
+                let mut s = s.lock().unwrap();
+                s.results = Some(results);
+                s.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        };
+        if crate::NO_THREADS {
+            run();
+        } else {
+            std::thread::spawn(run);
+        }
+        RunFuture { shared, policy }
+    }
+}
+
+impl<T> Future for RunFuture<T> {
+    type Output = Vec<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Vec<T>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.results.take() {
+            Some(Ok(results)) => {
+                drop(shared);
+                Poll::Ready(self.policy.resolve(results))
+            }
+            Some(Err(err)) => {
+                drop(shared);
+                std::panic::resume_unwind(err)
+            }
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for RunFuture<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunFuture")
+            .field("done", &self.shared.lock().unwrap().results.is_some())
+            .finish()
+    }
+}
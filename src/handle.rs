@@ -0,0 +1,125 @@
+//! Joining a batch started in the background.
+
+use std::fmt::Formatter;
+use std::thread::JoinHandle;
+
+use crate::PanicPolicy;
+
+/// A batch started by [`Parallel::spawn`](crate::Parallel::spawn).
+///
+/// Call [`ParallelHandle::join`] to wait for the closures and get their
+/// results, in the order they were added. Dropping the handle leaves the
+/// closures running in the background.
+#[must_use = "dropping the handle detaches the batch"]
+pub struct ParallelHandle<T> {
+    batch: Batch<T>,
+    policy: PanicPolicy,
+}
+
+/// The thread running a batch, or its outcome where it ran without one
+enum Batch<T> {
+    Thread(JoinHandle<Vec<std::thread::Result<T>>>),
+    Done(std::thread::Result<Vec<std::thread::Result<T>>>),
+}
+
+impl<T> ParallelHandle<T> {
+    /// Runs `f` on a new thread, or right away where there are none, whose
+    /// output is returned by `join`.
+    pub(crate) fn spawn<F>(policy: PanicPolicy, f: F) -> ParallelHandle<T>
+    where
+        F: FnOnce() -> Vec<std::thread::Result<T>> + Send + 'static,
+        T: Send + 'static
+    {
+        let batch = if crate::NO_THREADS {
+            Batch::Done(std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)))
+        } else {
+            Batch::Thread(std::thread::spawn(f))
+        };
+        ParallelHandle { batch, policy }
+    }
+
+    /// Returns `true` if all closures have finished.
+    pub fn is_finished(&self) -> bool {
+        match &self.batch {
+            Batch::Thread(thread) => thread.is_finished(),
+            Batch::Done(_) => true,
+        }
+    }
+
+    /// Waits for all closures and returns their results.
+    ///
+    /// If a closure panicked, the panic is resumed here, according to the
+    /// panic policy.
+    pub fn join(self) -> Vec<T> {
+        let outcome = match self.batch {
+            Batch::Thread(thread) => thread.join(),
+            Batch::Done(outcome) => outcome,
+        };
+        match outcome {
+            Ok(results) => self.policy.resolve(results),
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for ParallelHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParallelHandle")
+            .field("done", &self.is_finished())
+            .finish()
+    }
+}
+
+/// Joins a batch started by
+/// [`Parallel::spawn_streaming`](crate::Parallel::spawn_streaming) when
+/// dropped.
+///
+/// Dropping the guard waits for the closures and resumes a panic of theirs,
+/// unless the thread is already panicking. [`JoinGuard::join`] does the same
+/// at a point of your choosing.
+#[must_use = "dropping the guard waits for the batch right away"]
+pub struct JoinGuard {
+    handle: Option<ParallelHandle<()>>,
+}
+
+impl JoinGuard {
+    pub(crate) fn new(handle: ParallelHandle<()>) -> JoinGuard {
+        JoinGuard {
+            handle: Some(handle),
+        }
+    }
+
+    /// Returns `true` if all closures have finished.
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(ParallelHandle::is_finished)
+    }
+
+    /// Waits for all closures, resuming a panic if one of them panicked.
+    pub fn join(mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.join();
+        }
+    }
+}
+
+impl Drop for JoinGuard {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            if std::thread::panicking() {
+                if let Batch::Thread(thread) = handle.batch {
+                    let _ = thread.join();
+                }
+            } else {
+                handle.join();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for JoinGuard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinGuard")
+            .field("done", &self.is_finished())
+            .finish()
+    }
+}
@@ -0,0 +1,274 @@
+//! Running tasks as jobs that report back over a channel.
+
+use std::time::Instant;
+
+use crate::channel::{self, Receiver, Sender};
+use crate::sync::{Arc, AtomicBool, AtomicUsize, Mutex, Ordering};
+use crate::task::{self, Output, Queue, Runner, Task, Tasks};
+use crate::{Config, ThreadSettings};
+
+/// A job wrapping one task
+pub(crate) type TaskJob<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// Runs `job` on the thread started by `spawn`, or right here if the OS
+/// refuses one.
+pub(crate) fn spawn_or_run<'a, S>(spawn: S, job: TaskJob<'a>)
+where
+    S: FnOnce(TaskJob<'a>) -> std::io::Result<()>
+{
+    let job = Arc::new(Mutex::new(Some(job)));
+    let theirs = job.clone();
+    let spawned = spawn(Box::new(move || {
+        let job = theirs.lock().unwrap().take();
+        if let Some(job) = job {
+            job();
+        }
+    }));
+    #[cfg(feature = "metrics")]
+    if spawned.is_ok() {
+        metrics::counter!("easy_parallel_threads_spawned_total").increment(1);
+    }
+    if spawned.is_err() {
+        let job = job.lock().unwrap().take();
+        if let Some(job) = job {
+            job();
+        }
+    }
+}
+
+/// Hands the tasks of a batch to a backend as jobs, keeping at most
+/// `max_threads` of them in flight.
+///
+/// Under a limit each job keeps taking queued tasks once its own is done, so
+/// the queue drains without the caller having to call `recv`.
+pub(crate) struct JobQueue<'a, T> {
+    shared: Arc<Shared<'a, T>>,
+    receiver: Receiver<(usize, Output<T>)>,
+    remaining: usize,
+}
+
+/// The state shared by the jobs of a batch
+struct Shared<'a, T> {
+    tasks: Mutex<Queue<'a, T>>,
+    runner: Runner<'a>,
+    config: Config<'a>,
+    sender: Sender<(usize, Output<T>)>,
+    /// Whether jobs should take further tasks from the queue
+    limited: bool,
+    /// Set once the batch stops waiting, so queued tasks never start
+    closed: AtomicBool,
+    /// The jobs not yet dropped
+    live: AtomicUsize,
+    /// Whether jobs get threads of their own, which run the thread hooks
+    own_threads: bool,
+}
+
+impl<'a, T: Send + 'a> JobQueue<'a, T> {
+    /// Submits the first tasks to `spawn`, which is given the index of each job
+    /// in spawn order.
+    ///
+    /// With `own_threads`, each job runs the thread hooks around its tasks.
+    pub(crate) fn start(
+        tasks: Tasks<'a, T>,
+        config: &Config<'a>,
+        runner: &Runner<'a>,
+        spawn: &dyn Fn(usize, TaskJob<'a>),
+        own_threads: bool,
+    ) -> JobQueue<'a, T> {
+        let len = tasks.len();
+        let limit = config.max_threads().map_or(len, |n| n.min(len));
+        let (sender, receiver) = channel::channel();
+        let shared = Arc::new(Shared {
+            tasks: Mutex::new(task::start_order(tasks)),
+            runner: runner.clone(),
+            config: config.clone(),
+            sender,
+            limited: limit < len,
+            closed: AtomicBool::new(false),
+            live: AtomicUsize::new(limit),
+            own_threads,
+        });
+        for n in 0..limit {
+            if n > 0 {
+                config.stagger();
+            }
+            let first = shared.tasks.lock().unwrap().next();
+            let job = Job {
+                shared: shared.clone(),
+                thread: n,
+                first,
+            };
+            match &job.first {
+                Some(_) => spawn(n, Box::new(move || job.run())),
+                // An earlier job already took the rest
+                None => drop(job),
+            }
+        }
+        JobQueue {
+            shared,
+            receiver,
+            remaining: len,
+        }
+    }
+
+    /// Waits for the next task to finish, at most until `deadline`.
+    ///
+    /// Returns `None` once every task is done, or when the deadline passes.
+    pub(crate) fn recv(&mut self, deadline: Option<Instant>) -> Option<(usize, Output<T>)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let next = match deadline {
+            None => self.receiver.recv().ok(),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                self.receiver.recv_timeout(timeout).ok()
+            }
+        };
+        if next.is_some() {
+            self.remaining -= 1;
+        }
+        next
+    }
+}
+
+impl<T> Drop for JobQueue<'_, T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A job running its first task and, under a limit, whatever is queued after
+struct Job<'a, T> {
+    shared: Arc<Shared<'a, T>>,
+    /// The index of the job in spawn order, which its thread goes by
+    thread: usize,
+    first: Option<(usize, Task<'a, T>)>,
+}
+
+impl<'a, T> Job<'a, T> {
+    fn run(mut self) {
+        let (shared, thread) = (self.shared.clone(), self.thread);
+        if shared.own_threads {
+            shared.config.set_qos_class();
+            let init = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                shared.config.init_worker(thread)
+            }));
+            if let Err(err) = init {
+                // The hook's panic stands in for the task it kept from running
+                if let Some((i, _)) = self.first.take() {
+                    let _ = shared.sender.send((i, Output::One(Err(err))));
+                }
+                return;
+            }
+        }
+        let mut next = self.first.take();
+        let mut current = ThreadSettings::default();
+        while let Some((i, task)) = next {
+            current.switch(ThreadSettings {
+                priority: task.thread_priority.or(shared.config.thread_priority),
+                core: task.core.or_else(|| shared.config.core(thread)),
+            });
+            let output = shared.runner.run(i, task.work);
+            // The batch may have stopped waiting
+            let _ = shared.sender.send((i, output));
+            next = shared.next();
+        }
+        current.switch(ThreadSettings::default());
+        if shared.own_threads {
+            shared.config.exit_worker(thread);
+        }
+    }
+}
+
+impl<T> Shared<'_, T> {
+    /// Takes the next queued task, if jobs take more than their first.
+    fn next(&self) -> Option<(usize, Task<'_, T>)> {
+        if !self.limited || self.closed.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.tasks.lock().unwrap().next()
+    }
+}
+
+impl<T> Drop for Job<'_, T> {
+    /// Reports the tasks left without a job as panics, if the backend dropped
+    /// the job before it ran.
+    fn drop(&mut self) {
+        let dropped = || -> Box<dyn std::any::Any + Send> { Box::new("job was dropped before it ran") };
+        if let Some((i, _)) = self.first.take() {
+            let _ = self.shared.sender.send((i, Output::One(Err(dropped()))));
+        }
+        if self.shared.live.fetch_sub(1, Ordering::SeqCst) == 1 {
+            while let Some((i, _)) = self.shared.next() {
+                let _ = self.shared.sender.send((i, Output::One(Err(dropped()))));
+            }
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use super::{JobQueue, TaskJob};
+    use crate::task::{Output, Runner, Task, Tasks, Work};
+    use crate::Config;
+    use loom::thread;
+    use std::cell::RefCell;
+
+    /// Starts three tasks as two jobs, runs the first job unless `run_first`
+    /// is off and drops the rest, each on a thread of its own, then checks
+    /// that every task reported exactly once.
+    fn start_and_drop(run_first: bool) {
+        let config = Config {
+            max_threads: Some(2),
+            ..Config::default()
+        };
+        let tasks: Tasks<'static, usize> =
+            (0..3).map(|i| Task::new(Work::Call(Box::new(move || i)))).collect();
+        let runner = Runner::new(&config, tasks.len());
+        let jobs = RefCell::new(Vec::<TaskJob<'static>>::new());
+        let spawn = |_, job| jobs.borrow_mut().push(job);
+        let queue = JobQueue::start(tasks, &config, &runner, &spawn, false);
+
+        let threads: Vec<_> = jobs
+            .into_inner()
+            .into_iter()
+            .enumerate()
+            .map(|(n, job)| {
+                if n == 0 && run_first {
+                    thread::spawn(job)
+                } else {
+                    thread::spawn(move || drop(job))
+                }
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Every job is gone, so whatever it reported is already there
+        let mut seen = [false; 3];
+        for (i, output) in queue.receiver.try_iter() {
+            assert!(!seen[i]);
+            seen[i] = true;
+            match output {
+                Output::One(Ok(value)) => assert_eq!(value, i),
+                Output::One(Err(err)) => {
+                    assert_eq!(err.downcast_ref::<&str>(), Some(&"job was dropped before it ran"))
+                }
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(seen, [true; 3]);
+    }
+
+    #[test]
+    fn every_task_reports_once_when_a_job_is_dropped() {
+        loom::model(|| start_and_drop(true));
+    }
+
+    #[test]
+    fn the_last_dropped_job_reports_the_queued_tasks() {
+        loom::model(|| start_and_drop(false));
+    }
+}
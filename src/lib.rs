@@ -1,29 +1,521 @@
+use std::convert::TryFrom;
 use std::fmt::Formatter;
-use std::sync::mpsc;
+use std::iter::FromIterator;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+mod cancel;
+mod channel;
+mod future;
+mod handle;
+mod jobs;
+mod pipeline;
+mod pool;
+mod recursive;
+mod run_config;
+mod spawn;
+mod sync;
+mod sys;
+mod task;
+
+pub use cancel::is_cancelled;
+pub use future::RunFuture;
+pub use handle::{JoinGuard, ParallelHandle};
+pub use pipeline::Pipeline;
+pub use pool::ParallelPool;
+pub use recursive::WorkQueue;
+pub use run_config::RunConfig;
+pub use spawn::{Inline, Job, Spawn};
+
+use cancel::Cancel;
+use jobs::JobQueue;
+use spawn::JobScope;
+use task::{Output, Runner, Task, Tasks, Work, WrapFn};
+
+/// Runs each expression in parallel and returns their results in order.
+///
+/// `parallel! { a, b, c }` is shorthand for
+/// `Parallel::new().add(|| a).add(|| b).add(|| c).run()`.
+///
+/// ```
+/// use easy_parallel::parallel;
+///
+/// let v = vec![1, 2, 3];
+/// let results = parallel! {
+///     v.iter().sum::<i32>(),
+///     v.iter().product::<i32>(),
+///     v.len() as i32,
+/// };
+/// assert_eq!(results, [6, 6, 3]);
+/// ```
+#[macro_export]
+macro_rules! parallel {
+    ($($e:expr),* $(,)?) => {
+        $crate::Parallel::new()
+            $(.add(|| $e))*
+            .run()
+    };
+}
 
 #[derive(Default)]
 #[must_use]
 pub struct Parallel<'a, T> {
-    closures: Vec<Box<dyn FnOnce() -> T + Send + 'a>>
+    closures: Tasks<'a, T>,
+    config: Config<'a>,
+}
+
+/// A callback run on a worker thread
+type Hook<'a> = Arc<dyn Fn() + Send + Sync + 'a>;
+
+/// A callback told about a worker thread, by its index and id
+type ThreadHook<'a> = Arc<dyn Fn(usize, std::thread::ThreadId) + Send + Sync + 'a>;
+
+/// Settings applied to every closure of a batch
+#[derive(Clone, Default)]
+struct Config<'a> {
+    panic_policy: PanicPolicy,
+    thread_name: Option<Arc<dyn Fn(usize) -> String + Send + Sync + 'a>>,
+    stack_size: Option<usize>,
+    thread_priority: Option<ThreadPriority>,
+    cores: Vec<usize>,
+    max_threads: Option<usize>,
+    spawner: Option<Arc<dyn Spawn>>,
+    cancel: Option<Cancel>,
+    progress: Option<Arc<dyn Fn(usize, usize) + Send + Sync + 'a>>,
+    sequential: bool,
+    seed: Option<u64>,
+    start_together: bool,
+    stagger: Option<Duration>,
+    on_worker_init: Option<Hook<'a>>,
+    on_worker_exit: Option<Hook<'a>>,
+    on_thread_start: Option<ThreadHook<'a>>,
+    on_thread_stop: Option<ThreadHook<'a>>,
+    wrap: Option<WrapFn<'a>>,
+    panic_hook: Option<task::PanicHook<'a>>,
+    attribute_panics: bool,
+    no_abort: bool,
+    fail_fast: bool,
+    load_limit: bool,
+    qos_class: Option<QosClass>,
+}
+
+impl<'a> Config<'a> {
+    /// Returns the builder for the thread running the closure at `index`.
+    fn thread_builder(&self, index: usize) -> std::thread::Builder {
+        let mut builder = std::thread::Builder::new();
+        if let Some(name) = &self.thread_name {
+            builder = builder.name(name(index));
+        }
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+        builder
+    }
+
+    /// Spawns the worker thread `index`, which runs tasks from `next` until it
+    /// runs dry and puts the output of task `i` in `slots[i]`.
+    ///
+    /// Returns `None` if the OS refused the thread; `next` is dropped unused.
+    fn spawn_worker<'scope, 'env, T, N>(
+        &'scope self,
+        s: &'scope std::thread::Scope<'scope, 'env>,
+        runner: &'scope Runner<'a>,
+        slots: &'scope [Mutex<Option<Output<T>>>],
+        index: usize,
+        mut next: N,
+    ) -> Option<std::thread::ScopedJoinHandle<'scope, ()>>
+    where
+        N: FnMut() -> Option<(usize, Task<'a, T>)> + Send + 'scope,
+        T: Send + 'scope
+    {
+        let handle = self.thread_builder(index).spawn_scoped(s, move || {
+            let base = ThreadSettings {
+                priority: self.thread_priority,
+                core: self.core(index),
+            };
+            self.set_qos_class();
+            let init = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                self.init_worker(index)
+            }));
+            if let Err(err) = init {
+                // Drop the work this thread would have run, so nothing waits on it
+                while next().is_some() {}
+                std::panic::resume_unwind(err);
+            }
+            let mut current = ThreadSettings::default();
+            while let Some((i, task)) = next() {
+                current.switch(ThreadSettings {
+                    priority: task.thread_priority.or(base.priority),
+                    core: task.core.or(base.core),
+                });
+                let output = runner.run(i, task.work);
+                *slots[i].lock().unwrap() = Some(output);
+            }
+            self.exit_worker(index);
+        });
+        #[cfg(feature = "metrics")]
+        if handle.is_ok() {
+            metrics::counter!("easy_parallel_threads_spawned_total").increment(1);
+        }
+        handle.ok()
+    }
+
+    /// Sets the QoS class of the calling thread, if one was set.
+    fn set_qos_class(&self) {
+        if let Some(class) = self.qos_class {
+            sys::set_qos_class(class);
+        }
+    }
+
+    /// Runs the start hooks, on the thread `index` that was just spawned.
+    fn init_worker(&self, index: usize) {
+        if let Some(hook) = &self.on_thread_start {
+            hook(index, std::thread::current().id());
+        }
+        if let Some(hook) = &self.on_worker_init {
+            hook();
+        }
+    }
+
+    /// Runs the stop hooks, on the thread `index` that is about to exit.
+    fn exit_worker(&self, index: usize) {
+        if let Some(hook) = &self.on_worker_exit {
+            hook();
+        }
+        if let Some(hook) = &self.on_thread_stop {
+            hook(index, std::thread::current().id());
+        }
+    }
+
+    /// Wraps `job` in the hooks of thread `index`, which runs only that job.
+    fn hooked(&self, index: usize, job: jobs::TaskJob<'a>) -> jobs::TaskJob<'a> {
+        let no_hooks = self.on_worker_init.is_none()
+            && self.on_worker_exit.is_none()
+            && self.on_thread_start.is_none()
+            && self.on_thread_stop.is_none()
+            && self.qos_class.is_none();
+        if no_hooks {
+            return job;
+        }
+        let config = self.clone();
+        Box::new(move || {
+            config.set_qos_class();
+            config.init_worker(index);
+            job();
+            config.exit_worker(index);
+        })
+    }
+
+    /// Waits between two thread starts, as set by `stagger`.
+    fn stagger(&self) {
+        if let Some(delay) = self.stagger {
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Returns whether tasks run on the calling thread, as they do on `wasm32`
+    /// without thread support unless a spawner is set.
+    fn is_sequential(&self) -> bool {
+        self.sequential || (NO_THREADS && self.spawner.is_none())
+    }
+
+    /// Runs the tasks on the calling thread, in `add` order or in the order
+    /// given by the seed, until `deadline` passes.
+    ///
+    /// Returns the output of each task in `add` order, or `None` if it didn't
+    /// run in time.
+    fn run_sequential<T>(
+        &self,
+        runner: &Runner<'a>,
+        tasks: Tasks<'a, T>,
+        deadline: Option<Instant>,
+    ) -> Vec<Option<Output<T>>> {
+        let mut tasks: Vec<_> = tasks.into_iter().map(Some).collect();
+        let mut order: Vec<usize> = (0..tasks.len()).collect();
+        match self.seed {
+            Some(seed) => shuffle(seed, &mut order),
+            None => order.sort_by_key(|&i| std::cmp::Reverse(tasks[i].as_ref().unwrap().priority)),
+        }
+
+        let mut slots: Vec<_> = (0..tasks.len()).map(|_| None).collect();
+        for i in order {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                break;
+            }
+            let task = tasks[i].take().unwrap();
+            slots[i] = Some(runner.run(i, task.work));
+        }
+        slots
+    }
+
+    /// Returns the limit on threads at once, if any.
+    fn max_threads(&self) -> Option<usize> {
+        let limit = self.max_threads;
+        if !self.load_limit {
+            return limit;
+        }
+        // Leave the CPUs that other processes keep busy to them
+        let cpus = default_threads();
+        let busy = sys::load_average().map_or(0, |load| load.round() as usize);
+        let idle = cpus.saturating_sub(busy).max(1);
+        Some(limit.map_or(idle, |n| n.min(idle)))
+    }
+
+    /// Returns the core the thread at `index` is pinned to by `pin_cores`.
+    fn core(&self, index: usize) -> Option<usize> {
+        if self.cores.is_empty() {
+            None
+        } else {
+            Some(self.cores[index % self.cores.len()])
+        }
+    }
 }
 
 impl<'a, T> Parallel<'a, T> {
 
     pub fn new() -> Parallel<'a, T> {
         Parallel {
-            closures: Vec::new()
+            closures: Tasks::new(),
+            config: Config::default(),
+        }
+    }
+
+    /// Creates an empty batch with room for `n` closures.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::with_capacity(3).each(0..3, |i| i).run();
+    /// assert_eq!(v, [0, 1, 2]);
+    /// ```
+    pub fn with_capacity(n: usize) -> Parallel<'a, T> {
+        Parallel {
+            closures: Tasks::with_capacity(n),
+            config: Config::default(),
+        }
+    }
+
+    /// Creates a batch of already boxed closures, without boxing them again.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let fns: Vec<Box<dyn FnOnce() -> i32 + Send>> = vec![Box::new(|| 1), Box::new(|| 2)];
+    /// let v = Parallel::from_fns(fns).run();
+    /// assert_eq!(v, [1, 2]);
+    /// ```
+    pub fn from_fns(fns: Vec<Box<dyn FnOnce() -> T + Send + 'a>>) -> Parallel<'a, T> {
+        Parallel {
+            closures: fns.into_iter().map(|f| Task::new(Work::Call(f))).collect(),
+            config: Config::default(),
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn add<F>(mut self, f: F) -> Parallel<'a, T>
     where
         F: FnOnce() -> T + Send + 'a,
         T: Send + 'a
     {
-        self.closures.push(Box::new(f));
+        self.closures.push(Task::new(Work::Call(Box::new(f))));
+        self
+    }
+
+    /// Adds `f` only if `cond` is true.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let verbose = false;
+    /// let v = Parallel::new()
+    ///     .add(|| "build")
+    ///     .add_if(verbose, || "log")
+    ///     .run();
+    /// assert_eq!(v, ["build"]);
+    /// ```
+    pub fn add_if<F>(self, cond: bool, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a
+    {
+        if cond {
+            self.add(f)
+        } else {
+            self
+        }
+    }
+
+    /// Adds `f` if there is one.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let extra = Some(|| 2);
+    /// let v = Parallel::new().add(|| 1).add_some(extra).run();
+    /// assert_eq!(v, [1, 2]);
+    /// ```
+    pub fn add_some<F>(self, f: Option<F>) -> Parallel<'a, T>
+    where
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a
+    {
+        match f {
+            Some(f) => self.add(f),
+            None => self,
+        }
+    }
+
+    /// Adds a closure that runs on the calling thread, so it need not be
+    /// [`Send`].
+    ///
+    /// The batch becomes a [`WithLocal`], whose `run` runs the local closures
+    /// one after another on the calling thread while the others run on their
+    /// threads. Results keep the `add` order.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::rc::Rc;
+    ///
+    /// let here = Rc::new(std::thread::current().id());
+    /// let v = Parallel::new()
+    ///     .add(|| false)
+    ///     .add_local(move || *here == std::thread::current().id())
+    ///     .add(|| false)
+    ///     .run();
+    /// assert_eq!(v, [false, true, false]);
+    /// ```
+    pub fn add_local<F>(self, f: F) -> WithLocal<'a, T>
+    where
+        F: FnOnce() -> T + 'a,
+        T: Send + 'a
+    {
+        WithLocal {
+            parallel: self,
+            locals: Vec::new(),
+        }
+        .add_local(f)
+    }
+
+    /// Adds a closure that can emit any number of results through `emit`.
+    ///
+    /// Its results take its place among the results of the batch, in the order
+    /// they were emitted. [`Parallel::run_streaming`] yields each one right away,
+    /// and [`Parallel::run_catching`] keeps the ones emitted before a panic.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .add(|| 0)
+    ///     .add_producer(|emit| {
+    ///         for i in 1..4 {
+    ///             emit(i);
+    ///         }
+    ///     })
+    ///     .add(|| 4)
+    ///     .run();
+    /// assert_eq!(v, [0, 1, 2, 3, 4]);
+    /// ```
+    pub fn add_producer<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce(&mut dyn FnMut(T)) + Send + 'a,
+        T: Send + 'a
+    {
+        self.closures.push(Task::new(Work::Produce(Box::new(f))));
+        self
+    }
+
+    /// Adds every closure yielded by `iter`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().add_all((0..3).map(|i| move || i * 2)).run();
+    /// assert_eq!(v, [0, 2, 4]);
+    /// ```
+    pub fn add_all<I, F>(mut self, iter: I) -> Parallel<'a, T>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a
+    {
+        self.extend(iter);
+        self
+    }
+
+    /// Adds an already boxed closure, without boxing it again.
+    pub fn add_boxed(mut self, f: Box<dyn FnOnce() -> T + Send + 'a>) -> Parallel<'a, T> {
+        self.closures.push(Task::new(Work::Call(f)));
+        self
+    }
+
+    /// Adds a closure that starts before closures of lower `priority`.
+    ///
+    /// Closures are added with priority 0, and among equal priorities they
+    /// start in the order they were added. This matters when they can't all
+    /// start at once, as under [`max_threads`](Parallel::max_threads); results
+    /// are still returned in the order the closures were added. The last
+    /// closure of [`run`](Parallel::run) always runs on the calling thread.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::Mutex;
+    ///
+    /// let order = Mutex::new(Vec::new());
+    /// let v = Parallel::new()
+    ///     .max_threads(1)
+    ///     .add(|| order.lock().unwrap().push("bulk"))
+    ///     .add_with_priority(1, || order.lock().unwrap().push("critical"))
+    ///     .finish(|| ());
+    /// assert_eq!(v.0.len(), 2);
+    /// assert_eq!(order.into_inner().unwrap(), ["critical", "bulk"]);
+    /// ```
+    pub fn add_with_priority<F>(mut self, priority: i32, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a
+    {
+        let mut task = Task::new(Work::Call(Box::new(f)));
+        task.priority = priority;
+        self.closures.push(task);
+        self
+    }
+
+    /// Adds a closure whose thread runs at the given OS priority, overriding
+    /// [`Parallel::thread_priority`].
+    pub fn add_with_thread_priority<F>(mut self, priority: ThreadPriority, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a
+    {
+        let mut task = Task::new(Work::Call(Box::new(f)));
+        task.thread_priority = Some(priority);
+        self.closures.push(task);
+        self
+    }
+
+    /// Adds a closure whose thread is pinned to the CPU core with index `core`.
+    ///
+    /// See [`Parallel::pin_cores`] for platform support.
+    pub fn add_pinned<F>(mut self, core: usize, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a
+    {
+        let mut task = Task::new(Work::Call(Box::new(f)));
+        task.core = Some(core);
+        self.closures.push(task);
         self
     }
 
+    /// Adds one closure per item of `iter`, each calling `f` with its item.
+    ///
+    /// For many items, combine with [`Parallel::max_threads`]: the items then
+    /// wait in a shared queue, and a fixed set of threads keeps taking the next
+    /// one until the queue is empty, which balances items of uneven cost.
+    ///
+    /// Room for the closures is reserved up front from the iterator's size
+    /// hint, which is exact for an [`ExactSizeIterator`].
     pub fn each<A, I, F>(mut self, iter: I, f: F) -> Parallel<'a, T>
     where
         I: IntoIterator<Item = A>,
@@ -31,95 +523,3253 @@ impl<'a, T> Parallel<'a, T> {
         A: Send + 'a,
         T: Send + 'a
     {
-        for t in iter.into_iter() {
+        let iter = iter.into_iter();
+        self.closures.reserve(iter.size_hint().0);
+        for t in iter {
             let f = f.clone();
-            self.closures.push(Box::new(||f(t)));
+            self.closures.push(Task::new(Work::Call(Box::new(||f(t)))));
         }
         self
     }
 
-    pub fn run(mut self) -> Vec<T>
+    /// Like [`Parallel::each`], but also passes each item's position in `iter`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .each_enumerate(["a", "b"], |i, s| format!("{}{}", s, i))
+    ///     .run();
+    /// assert_eq!(v, ["a0", "b1"]);
+    /// ```
+    pub fn each_enumerate<A, I, F>(self, iter: I, f: F) -> Parallel<'a, T>
     where
+        I: IntoIterator<Item = A>,
+        F: FnOnce(usize, A) -> T + Clone + Send + 'a,
+        A: Send + 'a,
         T: Send + 'a
     {
-        let f = match self.closures.pop() {
-            None => return Vec::new(),
-            Some(f) => f,
-        };
-        let (mut results, r) = self.finish(f);
-        results.push(r);
-        results
+        self.each(iter.into_iter().enumerate(), move |(i, t)| f(i, t))
     }
 
-    pub fn finish<F, R>(self, f: F) -> (Vec<T>, R)
+    /// Adds `n` closures that each call `f`, such as independent trials of a
+    /// simulation.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().repeat(3, || "trial").run();
+    /// assert_eq!(v, ["trial"; 3]);
+    /// ```
+    pub fn repeat<F>(self, n: usize, f: F) -> Parallel<'a, T>
     where
-        F: FnOnce() -> R,
+        F: Fn() -> T + Send + Sync + 'a,
         T: Send + 'a
     {
-        // Set up a guard that aborts on panic
-        let guard = NoPanic;
-        let mut handles = Vec::new();
-        let mut receivers = Vec::new();
-        for f in self.closures.into_iter() {
-            let (sender, receiver) = mpsc::channel();
-            let f = move || sender.send(f()).unwrap();
-
-            // Erase the `'a` lifetime
-            let f: Box<dyn FnOnce() + Send + 'a>= Box::new(f);
-            let f: Box<dyn FnOnce() + Send + 'static> = unsafe {
-                std::mem::transmute(f)
+        let f = Arc::new(f);
+        self.each(0..n, move |_| f())
+    }
+
+    /// Like [`Parallel::each`], but `f` also gets a scratch value cloned from
+    /// `init`, such as a buffer or a connection.
+    ///
+    /// A closure takes an idle scratch value when it starts and puts it back
+    /// when it is done, so there are only about as many clones as closures
+    /// running at once, and each is reused across items. The scratch value of
+    /// a panicking closure is dropped.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .max_threads(2)
+    ///     .each_with_scratch(String::new(), 0..4, |buf, i| {
+    ///         buf.clear();
+    ///         buf.push_str(&i.to_string());
+    ///         buf.len()
+    ///     })
+    ///     .run();
+    /// assert_eq!(v, [1, 1, 1, 1]);
+    /// ```
+    pub fn each_with_scratch<S, A, I, F>(self, init: S, iter: I, f: F) -> Parallel<'a, T>
+    where
+        I: IntoIterator<Item = A>,
+        F: FnOnce(&mut S, A) -> T + Clone + Send + 'a,
+        S: Clone + Send + 'a,
+        A: Send + 'a,
+        T: Send + 'a
+    {
+        // The value to clone, and the scratch values not in use
+        let scratch = Arc::new(Mutex::new((init, Vec::new())));
+        self.each(iter, move |a| {
+            let mut s = {
+                let mut scratch = scratch.lock().unwrap();
+                let (init, idle) = &mut *scratch;
+                idle.pop().unwrap_or_else(|| init.clone())
             };
+            let t = f(&mut s, a);
+            scratch.lock().unwrap().1.push(s);
+            t
+        })
+    }
 
-            handles.push(std::thread::spawn(f));
-            receivers.push(receiver);
-        }
+    /// Splits `range` into `n` contiguous sub-ranges of about equal length, and
+    /// adds one closure per sub-range that gets it.
+    ///
+    /// Fewer than `n` closures are added if `range` is shorter than `n`, and
+    /// `n` is at least one.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().each_range(0..10, 3, |r| r).run();
+    /// assert_eq!(v, [0..4, 4..7, 7..10]);
+    /// ```
+    pub fn each_range<F>(self, range: std::ops::Range<usize>, n: usize, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce(std::ops::Range<usize>) -> T + Clone + Send + 'a,
+        T: Send + 'a
+    {
+        let len = range.len();
+        let n = n.max(1);
+        let (size, longer) = (len / n, len % n);
+        let start = range.start;
+        let bounds = move |k: usize| start + k * size + k.min(longer);
+        self.each(0..n.min(len), move |k| f(bounds(k)..bounds(k + 1)))
+    }
 
-        let mut last_err = None;
+    /// Adds one closure per available CPU, each calling `f` with its index.
+    ///
+    /// The number of closures is `EASY_PARALLEL_THREADS` if set, and otherwise
+    /// [`std::thread::available_parallelism`]. Combine with
+    /// [`Parallel::pin_cores`] to pin the threads to their cores.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().broadcast(|i| i).run();
+    /// assert_eq!(v, (0..v.len()).collect::<Vec<_>>());
+    /// ```
+    pub fn broadcast<F>(self, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce(usize) -> T + Clone + Send + 'a,
+        T: Send + 'a
+    {
+        self.each(0..default_threads(), f)
+    }
 
-        // Run the main closure on the main thread
-        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    /// Adds one closure per item of a borrowed collection, each calling `f`
+    /// with a reference to its item.
+    ///
+    /// The collection stays with the caller, so nothing is moved or cloned.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let words = vec!["a".to_string(), "bb".to_string()];
+    /// let v = Parallel::new().each_ref(&words, |s| s.len()).run();
+    /// assert_eq!(v, [1, 2]);
+    /// assert_eq!(words.len(), 2);
+    /// ```
+    pub fn each_ref<A, C, F>(self, items: &'a C, f: F) -> Parallel<'a, T>
+    where
+        C: ?Sized,
+        &'a C: IntoIterator<Item = &'a A>,
+        F: FnOnce(&'a A) -> T + Clone + Send + 'a,
+        A: Sync + 'a,
+        T: Send + 'a
+    {
+        self.each(items, f)
+    }
 
-        // Join threads and save the last panic if there was one
-        for h in handles {
-            if let Err(err) = h.join() {
-                last_err = Some(err);
+    /// Splits `slice` into `n` contiguous chunks of about equal length, and
+    /// adds one closure per chunk that gets it mutably.
+    ///
+    /// The chunks are disjoint, so each closure can change its part in place.
+    /// Fewer than `n` closures are added if `slice` is shorter than `n`, and
+    /// `n` is at least one.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let mut v: Vec<i32> = (0..10).collect();
+    /// let lens = Parallel::new()
+    ///     .each_mut_chunks(&mut v, 3, |chunk| {
+    ///         chunk.iter_mut().for_each(|x| *x *= 2);
+    ///         chunk.len()
+    ///     })
+    ///     .run();
+    /// assert_eq!(lens, [4, 3, 3]);
+    /// assert_eq!(v, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+    /// ```
+    pub fn each_mut_chunks<E, F>(self, slice: &'a mut [E], n: usize, f: F) -> Parallel<'a, T>
+    where
+        F: FnOnce(&'a mut [E]) -> T + Clone + Send + 'a,
+        E: Send,
+        T: Send + 'a
+    {
+        let n = n.max(1);
+        let (size, longer) = (slice.len() / n, slice.len() % n);
+        let mut chunks = Vec::with_capacity(n);
+        let mut rest = slice;
+        for k in 0..n {
+            let len = size + usize::from(k < longer);
+            if len == 0 {
+                break;
             }
+            let (chunk, tail) = rest.split_at_mut(len);
+            chunks.push(chunk);
+            rest = tail;
         }
-        drop(guard);
+        self.each(chunks, f)
+    }
 
-        // if a thread has panicked resume the last collected panic
-        if let Some(err) = last_err {
-            std::panic::resume_unwind(err);
+    /// Passes the result of every closure added so far through `f`, on the
+    /// thread that ran the closure.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .each(1..=3, |i| i)
+    ///     .map(|i| i.to_string())
+    ///     .add(|| "done".to_string())
+    ///     .run();
+    /// assert_eq!(v, ["1", "2", "3", "done"]);
+    /// ```
+    pub fn map<U, F>(self, f: F) -> Parallel<'a, U>
+    where
+        F: FnOnce(T) -> U + Clone + Send + 'a,
+        T: 'a
+    {
+        let Parallel { closures, config } = self;
+        Parallel {
+            closures: closures.into_iter().map(|task| task.map(f.clone())).collect(),
+            config,
         }
+    }
+
+    /// Calls `f` with the index and result of every closure added so far, on
+    /// the thread that ran the closure, as soon as it finishes.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::Mutex;
+    ///
+    /// let log = Mutex::new(Vec::new());
+    /// let v = Parallel::new()
+    ///     .each(0..3, |i| i * 10)
+    ///     .inspect(|i, t| log.lock().unwrap().push((i, *t)))
+    ///     .run();
+    /// assert_eq!(v, [0, 10, 20]);
+    /// let mut log = log.into_inner().unwrap();
+    /// log.sort();
+    /// assert_eq!(log, [(0, 0), (1, 10), (2, 20)]);
+    /// ```
+    pub fn inspect<F>(self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn(usize, &T) + Send + Sync + 'a,
+        T: 'a
+    {
+        let f = Arc::new(f);
+        let Parallel { closures, config } = self;
+        let closures = closures
+            .into_iter()
+            .enumerate()
+            .map(|(i, task)| {
+                let f = f.clone();
+                task.map(move |t| {
+                    f(i, &t);
+                    t
+                })
+            })
+            .collect();
+        Parallel { closures, config }
+    }
 
-        let mut results = Vec::new();
-        for receiver in receivers {
-            results.push(receiver.recv().unwrap());
+    /// Pairs the results of this batch with those of `other`, by position,
+    /// running the closures of both in one batch.
+    ///
+    /// The settings of this batch apply to all closures. As with
+    /// [`Iterator::zip`], extra results of the longer side are dropped.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let names = Parallel::new().each(vec!["a", "b"], |s| s.to_uppercase());
+    /// let lens = Parallel::new().each(vec!["xx", "y"], |s| s.len());
+    /// assert_eq!(names.zip(lens).run(), [("A".to_string(), 2), ("B".to_string(), 1)]);
+    /// ```
+    pub fn zip<U>(self, other: Parallel<'a, U>) -> Zip<'a, T, U>
+    where
+        T: 'a,
+        U: 'a
+    {
+        Zip {
+            parallel: self.map(Side::Left).chain(other.map(Side::Right)),
         }
+    }
 
-        // If the main closure panicked, resume its panic
-        match res {
-            Ok(r) => (results, r),
-            Err(err) => std::panic::resume_unwind(err)
+    /// Appends the closures of `other` to this batch.
+    ///
+    /// The settings of this batch apply to all closures; those of `other` are
+    /// dropped.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// fn squares<'a>() -> Parallel<'a, i32> {
+    ///     Parallel::new().each(1..=3, |i| i * i)
+    /// }
+    ///
+    /// let v = Parallel::new().add(|| 0).chain(squares()).run();
+    /// assert_eq!(v, [0, 1, 4, 9]);
+    /// ```
+    ///
+    /// A batch of `'static` closures, such as one set up for a pool, also
+    /// chains onto a batch that borrows:
+    ///
+    /// ```
+    /// use easy_parallel::{Parallel, ParallelPool};
+    ///
+    /// fn statics() -> Parallel<'static, i32> {
+    ///     ParallelPool::new(2).batch().each(1..=2, |i| i)
+    /// }
+    /// # fn shorten<'a>(p: Parallel<'static, i32>) -> Parallel<'a, i32> {
+    /// #     p
+    /// # }
+    ///
+    /// let zero = 0;
+    /// let v = Parallel::new().add(|| zero).chain(statics()).run();
+    /// assert_eq!(v, [0, 1, 2]);
+    /// let zipped = Parallel::new().add(|| &zero).zip(statics()).run();
+    /// assert_eq!(zipped, [(&0, 1)]);
+    /// ```
+    pub fn chain(mut self, other: Parallel<'a, T>) -> Parallel<'a, T> {
+        self.closures.extend(other.closures);
+        self
+    }
+
+    /// Shares `state` with the closures added through the returned
+    /// [`WithState`], which each get a `&S`.
+    ///
+    /// The state is put in an [`Arc`] once, instead of cloning one before
+    /// every `add`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .with_state(vec![10, 20, 30])
+    ///     .add_with(|v| v.len())
+    ///     .each_with(0..3, |v, i| v[i])
+    ///     .run();
+    /// assert_eq!(v, [3, 10, 20, 30]);
+    /// ```
+    pub fn with_state<S>(self, state: S) -> WithState<'a, T, S>
+    where
+        S: Send + Sync + 'a
+    {
+        WithState {
+            parallel: self,
+            state: Arc::new(state),
         }
     }
-}
 
-impl<T> std::fmt::Debug for Parallel<'_, T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Parallel")
-            .field("len", &self.closures.len())
-            .finish()
+    /// Calls `f` with the index and payload of every closure that panics, for
+    /// logging or crash reports.
+    ///
+    /// `f` runs on the thread of the closure once it has unwound, and the
+    /// panic is then handled under the panic policy as usual. The global panic
+    /// hook is left alone, so it still reports the panic first.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let seen = Arc::new(AtomicUsize::new(0));
+    /// let counter = seen.clone();
+    /// let v = Parallel::new()
+    ///     .panic_hook(move |index, payload| {
+    ///         assert_eq!(index, 1);
+    ///         assert_eq!(payload.downcast_ref::<&str>(), Some(&"oops"));
+    ///         counter.fetch_add(1, Ordering::SeqCst);
+    ///     })
+    ///     .add(|| 1)
+    ///     .add(|| panic!("oops"))
+    ///     .run_catching();
+    /// assert!(v[1].is_err());
+    /// assert_eq!(seen.load(Ordering::SeqCst), 1);
+    /// ```
+    pub fn panic_hook<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn(usize, &(dyn std::any::Any + Send)) + Send + Sync + 'a
+    {
+        self.config.panic_hook = Some(Arc::new(f));
+        self
     }
-}
 
-/// Aborts the process if dropped while panicking
-struct NoPanic;
+    /// Wraps the payload of every panicking closure in a [`ClosurePanic`],
+    /// which tells which closure it was.
+    ///
+    /// The wrapped payload is what gets resumed or returned under the panic
+    /// policy.
+    ///
+    /// ```
+    /// use easy_parallel::{ClosurePanic, Parallel};
+    ///
+    /// let v = Parallel::new()
+    ///     .attribute_panics()
+    ///     .add(|| ())
+    ///     .add(|| panic!("oops"))
+    ///     .run_catching();
+    /// let err = v[1].as_ref().unwrap_err();
+    /// let panic = err.downcast_ref::<ClosurePanic>().unwrap();
+    /// assert_eq!(panic.index(), 1);
+    /// assert_eq!(panic.message(), Some("oops"));
+    /// ```
+    pub fn attribute_panics(mut self) -> Parallel<'a, T> {
+        self.config.attribute_panics = true;
+        self
+    }
 
-impl Drop for NoPanic {
-    fn drop(&mut self) {
-        if std::thread::panicking() {
-            std::process::abort();
+    /// Applies the settings of `config`, keeping the ones it leaves unset.
+    ///
+    /// See [`RunConfig`].
+    pub fn with_config(self, config: &RunConfig) -> Parallel<'a, T> {
+        config.apply(self)
+    }
+
+    /// Sets what happens when one of the closures panics.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Parallel<'a, T> {
+        self.config.panic_policy = policy;
+        self
+    }
+
+    /// Stops the batch as soon as a closure panics.
+    ///
+    /// Closures that haven't started by then are skipped, and [`is_cancelled`]
+    /// tells the running ones to stop. The panic is still propagated under the
+    /// panic policy once the running closures are done. In
+    /// [`Parallel::run_catching`], skipped closures keep their place with an
+    /// `Err` whose payload is `"closure was cancelled before it ran"`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .fail_fast()
+    ///     .max_threads(1)
+    ///     .each(0..100, |i| if i == 3 { panic!("bad item") } else { i })
+    ///     .run_catching();
+    /// assert_eq!(v.len(), 100);
+    /// assert!(v[3].is_err());
+    /// let skipped = v[4].as_ref().unwrap_err();
+    /// assert_eq!(skipped.downcast_ref::<&str>(), Some(&"closure was cancelled before it ran"));
+    /// ```
+    pub fn fail_fast(mut self) -> Parallel<'a, T> {
+        self.config.fail_fast = true;
+        self
+    }
+
+    /// Lets a panic that escapes while the threads are running unwind instead
+    /// of aborting the process.
+    ///
+    /// Closures can't cause this, since their panics are always caught, but
+    /// [`thread_name`](Parallel::thread_name), which runs on the calling
+    /// thread while it spawns the others, can. By default the process aborts
+    /// then; with this set the panic is resumed once every thread is joined.
+    /// [`PanicPolicy::Abort`] still aborts.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let res = std::panic::catch_unwind(|| {
+    ///     Parallel::new()
+    ///         .no_abort()
+    ///         .thread_name(|_| panic!("no names"))
+    ///         .each(0..2, |i| i)
+    ///         .run()
+    /// });
+    /// assert!(res.is_err());
+    /// ```
+    pub fn no_abort(mut self) -> Parallel<'a, T> {
+        self.config.no_abort = true;
+        self
+    }
+
+    /// Names the spawned threads, given the index of the closure each one runs.
+    ///
+    /// Under [`Parallel::max_threads`] threads run several closures, and the
+    /// index is that of the thread instead.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::thread;
+    ///
+    /// let names = Parallel::new()
+    ///     .thread_name(|i| format!("worker-{}", i))
+    ///     .each(0..2, |_| thread::current().name().map(String::from))
+    ///     .finish(|| ())
+    ///     .0;
+    /// assert_eq!(names, [Some("worker-0".into()), Some("worker-1".into())]);
+    ///
+    /// let name = || thread::current().name().map(String::from);
+    /// let names = Parallel::new()
+    ///     .max_threads(2)
+    ///     .thread_name(|i| format!("worker-{}", i))
+    ///     .each(0..3, move |_| name())
+    ///     .add_with_priority(1, name)
+    ///     .run_timeout(std::time::Duration::from_secs(5))
+    ///     .results;
+    /// assert_eq!(names.len(), 4);
+    /// assert!(names.iter().flatten().all(|name| name == "worker-0" || name == "worker-1"));
+    /// ```
+    pub fn thread_name<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn(usize) -> String + Send + Sync + 'a
+    {
+        self.config.thread_name = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the stack size in bytes of the spawned threads.
+    ///
+    /// The closure run on the calling thread keeps the caller's stack.
+    pub fn stack_size(mut self, size: usize) -> Parallel<'a, T> {
+        self.config.stack_size = Some(size);
+        self
+    }
+
+    /// Sets the quality of service class of the spawned threads on Apple
+    /// platforms, such as [`QosClass::Background`] to keep batch work on the
+    /// efficiency cores.
+    ///
+    /// It has no effect on other platforms, on the calling thread, or on the
+    /// threads of a backend set with [`Parallel::with_spawner`].
+    ///
+    /// ```
+    /// use easy_parallel::{Parallel, QosClass};
+    ///
+    /// let v = Parallel::new().qos_class(QosClass::Utility).each(0..2, |i| i).run();
+    /// assert_eq!(v, [0, 1]);
+    /// ```
+    pub fn qos_class(mut self, class: QosClass) -> Parallel<'a, T> {
+        self.config.qos_class = Some(class);
+        self
+    }
+
+    /// Sets the OS priority of the spawned threads.
+    ///
+    /// This is best effort: raising the priority usually needs extra
+    /// privileges, and failures are ignored. The closure run on the calling
+    /// thread keeps the caller's priority.
+    ///
+    /// On Linux this also means a thread can't get back a priority it
+    /// lowered, so lowering is one-way on threads that run more than one
+    /// closure: a queue thread under [`Parallel::max_threads`] keeps the
+    /// lowest priority of the closures it ran, and so does a thread of a
+    /// backend set with [`Parallel::with_spawner`] after the batch is done.
+    ///
+    /// ```
+    /// use easy_parallel::{Parallel, ThreadPriority};
+    ///
+    /// # #[cfg(target_os = "linux")]
+    /// let nice = || unsafe {
+    ///     libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t)
+    /// };
+    /// # #[cfg(not(target_os = "linux"))]
+    /// # let nice = || 10;
+    /// let v = Parallel::new()
+    ///     .thread_priority(ThreadPriority::BelowNormal)
+    ///     .add(nice)
+    ///     .add(nice)
+    ///     .run();
+    /// // Nice 10, unless the caller already runs at a lower priority
+    /// let (spawned, caller) = (v[0], v[1]);
+    /// assert!(spawned == 10 || (caller > 10 && spawned == caller));
+    /// ```
+    pub fn thread_priority(mut self, priority: ThreadPriority) -> Parallel<'a, T> {
+        self.config.thread_priority = Some(priority);
+        self
+    }
+
+    /// Pins the spawned threads to CPU cores, cycling through `cores` in spawn
+    /// order. Closures added with [`Parallel::add_pinned`] keep their own core.
+    ///
+    /// Pinning is supported on Linux, Android and Windows, and ignored elsewhere.
+    /// Cores the OS can't address are ignored too.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().pin_cores([0, 5000]).each(0..3, |i| i).run();
+    /// assert_eq!(v, [0, 1, 2]);
+    /// ```
+    pub fn pin_cores<I>(mut self, cores: I) -> Parallel<'a, T>
+    where
+        I: IntoIterator<Item = usize>
+    {
+        self.config.cores = cores.into_iter().collect();
+        self
+    }
+
+    /// Pins the spawned threads to CPU cores taken from each NUMA node in
+    /// turn, so consecutive threads land on different nodes.
+    ///
+    /// Memory-bound batches then use the memory bandwidth of every node. This
+    /// is [`Parallel::pin_cores`] with the cores interleaved by node. The nodes
+    /// are read from sysfs on Linux; elsewhere, and on machines with a single
+    /// node, nothing is pinned.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().spread_numa().each(0..4, |i| i).run();
+    /// assert_eq!(v, [0, 1, 2, 3]);
+    /// ```
+    pub fn spread_numa(mut self) -> Parallel<'a, T> {
+        let nodes = sys::numa_nodes();
+        if nodes.len() > 1 {
+            let longest = nodes.iter().map(Vec::len).max().unwrap_or(0);
+            self.config.cores = (0..longest)
+                .flat_map(|k| nodes.iter().filter_map(move |node| node.get(k).copied()))
+                .collect();
         }
+        self
+    }
+
+    /// Calls `f` with the number of finished closures and the total each time
+    /// a closure finishes.
+    ///
+    /// `f` runs on the thread that ran the closure, possibly on several threads
+    /// at once, so calls may arrive slightly out of order. A panic in `f`
+    /// counts as a panic of the closure.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// let calls = AtomicUsize::new(0);
+    /// Parallel::new()
+    ///     .on_progress(|_done, total| {
+    ///         assert_eq!(total, 5);
+    ///         calls.fetch_add(1, Ordering::SeqCst);
+    ///     })
+    ///     .each(0..5, |i| i)
+    ///     .run();
+    /// assert_eq!(calls.load(Ordering::SeqCst), 5);
+    /// ```
+    pub fn on_progress<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn(usize, usize) + Send + Sync + 'a
+    {
+        self.config.progress = Some(Arc::new(f));
+        self
+    }
+
+    /// Runs the closures on at most `n` spawned threads at once.
+    ///
+    /// Closures that don't get a thread right away are queued and picked up by
+    /// the first thread that becomes free, so a slow closure never holds up the
+    /// ones queued behind it, unlike with [`Parallel::each_chunked`]. Results
+    /// keep the `add` order. `n` is at least one.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .max_threads(4)
+    ///     .each(0..1000, |i| i * 2)
+    ///     .run();
+    /// assert_eq!(v, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+    /// ```
+    pub fn max_threads(mut self, n: usize) -> Parallel<'a, T> {
+        self.config.max_threads = Some(n.max(1));
+        self
+    }
+
+    /// Limits the threads at once to the number of CPUs idle when the batch
+    /// starts, instead of one thread per closure.
+    ///
+    /// The CPUs are counted against the system's load average once, at the
+    /// start, so a busy machine gets fewer threads, and never more than
+    /// [`max_threads`](Parallel::max_threads). The limit then holds for the
+    /// whole batch, even if the load changes while it runs: the load average
+    /// moves over a minute, slower than most batches, so threads aren't added
+    /// or retired as they take closures. Where the load average is unknown, as
+    /// on Windows, every available CPU counts as idle. A positive
+    /// `EASY_PARALLEL_THREADS` replaces the number of available CPUs.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().max_threads_from_load().each(0..100, |i| i).run();
+    /// assert_eq!(v, (0..100).collect::<Vec<_>>());
+    /// ```
+    pub fn max_threads_from_load(mut self) -> Parallel<'a, T> {
+        self.config.load_limit = true;
+        self
+    }
+
+    /// Runs the closures one after another on the calling thread, in `add`
+    /// order, instead of in parallel.
+    ///
+    /// Closures added with [`add_with_priority`](Parallel::add_with_priority)
+    /// go by priority first, higher ones earlier, and keep `add` order among
+    /// equal priorities. The last closure of [`run`](Parallel::run) still runs
+    /// last, whatever its priority.
+    ///
+    /// Results, panics and cancellation behave as they do in parallel, which
+    /// makes this handy for telling data races apart from plain bugs. No
+    /// threads are spawned, so thread names, stack sizes, priorities and cores
+    /// are ignored, and so is the spawner set with [`with_spawner`].
+    ///
+    /// This is the default on `wasm32` targets without thread support, such
+    /// as the browser; to use Web Workers there, plug them in as a spawner.
+    /// The background terminators, [`spawn`], [`spawn_streaming`],
+    /// [`run_async`] and [`detach`], then run the whole batch before they
+    /// return, and a [`ParallelPool`] runs its jobs on the calling thread.
+    ///
+    /// [`with_spawner`]: Parallel::with_spawner
+    /// [`spawn`]: Parallel::spawn
+    /// [`spawn_streaming`]: Parallel::spawn_streaming
+    /// [`run_async`]: Parallel::run_async
+    /// [`detach`]: Parallel::detach
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::Mutex;
+    ///
+    /// let order = Mutex::new(Vec::new());
+    /// let v = Parallel::new()
+    ///     .sequential()
+    ///     .each(0..4, |i| {
+    ///         order.lock().unwrap().push(i);
+    ///         i * 2
+    ///     })
+    ///     .run();
+    /// assert_eq!(v, [0, 2, 4, 6]);
+    /// assert_eq!(order.into_inner().unwrap(), [0, 1, 2, 3]);
+    ///
+    /// let order = Mutex::new(Vec::new());
+    /// Parallel::new()
+    ///     .sequential()
+    ///     .add(|| order.lock().unwrap().push("bulk"))
+    ///     .add_with_priority(1, || order.lock().unwrap().push("critical"))
+    ///     .add(|| order.lock().unwrap().push("last"))
+    ///     .run();
+    /// assert_eq!(order.into_inner().unwrap(), ["critical", "bulk", "last"]);
+    /// ```
+    pub fn sequential(mut self) -> Parallel<'a, T> {
+        self.config.sequential = true;
+        self
+    }
+
+    /// Runs the closures one after another on the calling thread, in an order
+    /// shuffled by `seed`.
+    ///
+    /// This is [`sequential`](Parallel::sequential) with a reproducible start
+    /// order: the same seed and closures always run in the same order, so
+    /// consumers such as [`run_streaming`](Parallel::run_streaming) see results
+    /// in the same order too. Results of [`run`](Parallel::run) are still in
+    /// `add` order.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::Mutex;
+    ///
+    /// let order = |seed| {
+    ///     let order = Mutex::new(Vec::new());
+    ///     let v = Parallel::new()
+    ///         .seeded(seed)
+    ///         .each(0..8, |i| order.lock().unwrap().push(i))
+    ///         .run();
+    ///     assert_eq!(v.len(), 8);
+    ///     order.into_inner().unwrap()
+    /// };
+    /// assert_eq!(order(7), order(7));
+    /// ```
+    pub fn seeded(mut self, seed: u64) -> Parallel<'a, T> {
+        self.config.sequential = true;
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Holds every spawned thread back until all of them are up, then lets
+    /// them start their closures together.
+    ///
+    /// Handy for benchmarks and contention tests, where threads spawned one
+    /// after another would otherwise get a head start. This applies to the
+    /// default backend; the closure run on the calling thread starts once the
+    /// others are released.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::time::Instant;
+    ///
+    /// let starts = Parallel::new()
+    ///     .start_together()
+    ///     .each(0..4, |_| Instant::now())
+    ///     .run();
+    /// assert_eq!(starts.len(), 4);
+    /// ```
+    ///
+    /// If spawning a thread panics, as in a [`thread_name`](Parallel::thread_name)
+    /// callback, the threads already up are let go so the panic reaches the
+    /// caller:
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::panic;
+    ///
+    /// let res = panic::catch_unwind(|| {
+    ///     Parallel::new()
+    ///         .start_together()
+    ///         .no_abort()
+    ///         .thread_name(|i| if i == 1 { panic!("no name") } else { format!("w{}", i) })
+    ///         .each(0..4, |i| i)
+    ///         .run()
+    /// });
+    /// assert!(res.is_err());
+    /// ```
+    pub fn start_together(mut self) -> Parallel<'a, T> {
+        self.config.start_together = true;
+        self
+    }
+
+    /// Runs `f` on every thread the batch spawns, before it runs any closure.
+    ///
+    /// Use it to set up thread-local state such as allocator arenas, RNGs or
+    /// FFI libraries. Closures run on the calling thread or by a spawner don't
+    /// get a hook. A panic in `f` is resumed once the batch has finished.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::cell::Cell;
+    ///
+    /// thread_local!(static READY: Cell<bool> = Cell::new(false));
+    ///
+    /// let v = Parallel::new()
+    ///     .on_worker_init(|| READY.with(|r| r.set(true)))
+    ///     .each(0..3, |_| READY.with(Cell::get))
+    ///     .finish(|| ())
+    ///     .0;
+    /// assert_eq!(v, [true, true, true]);
+    /// ```
+    pub fn on_worker_init<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn() + Send + Sync + 'a
+    {
+        self.config.on_worker_init = Some(Arc::new(f));
+        self
+    }
+
+    /// Runs `f` on every thread the batch spawns, after its last closure.
+    ///
+    /// The counterpart of [`on_worker_init`](Parallel::on_worker_init).
+    pub fn on_worker_exit<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn() + Send + Sync + 'a
+    {
+        self.config.on_worker_exit = Some(Arc::new(f));
+        self
+    }
+
+    /// Calls `f` with the index and id of every thread the batch spawns, as
+    /// soon as it starts.
+    ///
+    /// The index is the one given to [`thread_name`](Parallel::thread_name).
+    /// Like the worker hooks, this is for threads the batch spawns itself.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::Mutex;
+    ///
+    /// let started = Mutex::new(Vec::new());
+    /// Parallel::new()
+    ///     .on_thread_start(|i, _id| started.lock().unwrap().push(i))
+    ///     .each(0..3, |i| i)
+    ///     .run();
+    /// let mut started = started.into_inner().unwrap();
+    /// started.sort();
+    /// assert_eq!(started, [0, 1]);
+    /// ```
+    pub fn on_thread_start<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn(usize, std::thread::ThreadId) + Send + Sync + 'a
+    {
+        self.config.on_thread_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Calls `f` with the index and id of every thread the batch spawns, just
+    /// before it exits.
+    ///
+    /// The counterpart of [`on_thread_start`](Parallel::on_thread_start).
+    pub fn on_thread_stop<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn(usize, std::thread::ThreadId) + Send + Sync + 'a
+    {
+        self.config.on_thread_stop = Some(Arc::new(f));
+        self
+    }
+
+    /// Runs every closure through the middleware `f`, which gets the index of
+    /// the closure and a `run` function that runs it.
+    ///
+    /// `f` runs on the closure's thread and must call `run` once, for example
+    /// to time or log the closure. `run` returns whether the closure panicked,
+    /// but not its result or payload, which are handled as usual and can't be
+    /// replaced by `f`. A panic in `f` counts as a panic of the closure.
+    ///
+    /// Each call to `wrap` adds a middleware around the ones before it, and
+    /// applies to all closures, whenever they were added.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::Mutex;
+    ///
+    /// let log = Mutex::new(Vec::new());
+    /// let v = Parallel::new()
+    ///     .wrap(|i, run| {
+    ///         let panicked = run();
+    ///         log.lock().unwrap().push((i, panicked));
+    ///     })
+    ///     .add(|| 1)
+    ///     .add(|| panic!("oops"))
+    ///     .run_catching();
+    /// assert!(v[1].is_err());
+    /// let mut log = log.into_inner().unwrap();
+    /// log.sort();
+    /// assert_eq!(log, [(0, false), (1, true)]);
+    /// ```
+    pub fn wrap<F>(mut self, f: F) -> Parallel<'a, T>
+    where
+        F: Fn(usize, &mut dyn FnMut() -> bool) + Send + Sync + 'a
+    {
+        self.config.wrap = Some(match self.config.wrap.take() {
+            None => Arc::new(f),
+            Some(inner) => Arc::new(move |i, run: &mut dyn FnMut() -> bool| {
+                f(i, &mut || {
+                    let mut panicked = false;
+                    inner(i, &mut || {
+                        panicked = run();
+                        panicked
+                    });
+                    panicked
+                })
+            }),
+        });
+        self
+    }
+
+    /// Carries `context` from the calling thread into every closure.
+    ///
+    /// `install` runs on the closure's thread before the closure, and the
+    /// guard it returns is dropped right after, which is where it should put
+    /// back whatever was there before. This fits thread-locals such as request
+    /// ids, or `tracing::dispatcher::set_default`. Like
+    /// [`wrap`](Parallel::wrap), it applies to all closures.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::cell::Cell;
+    ///
+    /// thread_local! {
+    ///     static REQUEST: Cell<u64> = Cell::new(0);
+    /// }
+    ///
+    /// struct Restore(u64);
+    /// impl Drop for Restore {
+    ///     fn drop(&mut self) {
+    ///         REQUEST.with(|r| r.set(self.0));
+    ///     }
+    /// }
+    ///
+    /// REQUEST.with(|r| r.set(42));
+    /// let v = Parallel::new()
+    ///     .carry(REQUEST.with(Cell::get), |&id| Restore(REQUEST.with(|r| r.replace(id))))
+    ///     .each(0..3, |_| REQUEST.with(Cell::get))
+    ///     .run();
+    /// assert_eq!(v, [42, 42, 42]);
+    /// ```
+    pub fn carry<C, G, F>(self, context: C, install: F) -> Parallel<'a, T>
+    where
+        F: Fn(&C) -> G + Send + Sync + 'a,
+        C: Send + Sync + 'a
+    {
+        self.wrap(move |_, run| {
+            let _guard = install(&context);
+            run();
+        })
+    }
+
+    /// Waits `delay` between starting one thread and the next.
+    ///
+    /// Spreads out the start of a batch whose closures would otherwise all hit
+    /// the same service at once. The calling thread's closure starts last,
+    /// `delay` after the final thread. Under
+    /// [`max_threads`](Parallel::max_threads) only the first threads are
+    /// staggered; later closures start as earlier ones finish.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let start = Instant::now();
+    /// Parallel::new()
+    ///     .stagger(Duration::from_millis(20))
+    ///     .each(0..3, |_| ())
+    ///     .run();
+    /// assert!(start.elapsed() >= Duration::from_millis(40));
+    /// ```
+    pub fn stagger(mut self, delay: Duration) -> Parallel<'a, T> {
+        self.config.stagger = Some(delay);
+        self
+    }
+
+    /// Returns the number of closures added so far.
+    ///
+    /// A closure from [`add_producer`](Parallel::add_producer) counts once,
+    /// however many values it emits.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let p = Parallel::new().each(0..3, |i| i);
+    /// assert_eq!(p.len(), 3);
+    /// # p.run();
+    /// ```
+    pub fn len(&self) -> usize {
+        self.closures.len()
+    }
+
+    /// Returns `true` if no closures have been added.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// assert!(Parallel::<()>::new().is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.closures.is_empty()
+    }
+
+    /// Runs all closures and returns their results.
+    ///
+    /// Results are returned in exactly the order the closures were added. The
+    /// last added closure runs on the calling thread, the others each get their
+    /// own thread. If the OS refuses to spawn a thread, the closures left
+    /// without one run on the calling thread instead.
+    ///
+    /// With the default backend, each thread writes its results straight into
+    /// a slot set aside for them, without a channel, so a batch of closures
+    /// returning `()` only pays for catching their panics. Backends set with
+    /// [`Parallel::with_spawner`] send results back over a channel instead.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// // Earlier closures finish last, yet their results still come first
+    /// let v = Parallel::new()
+    ///     .add(|| 1)
+    ///     .each(2..=4, |i| {
+    ///         thread::sleep(Duration::from_millis(10 * (5 - i)));
+    ///         i
+    ///     })
+    ///     .add(|| 5)
+    ///     .run();
+    /// assert_eq!(v, [1, 2, 3, 4, 5]);
+    /// ```
+    pub fn run(self) -> Vec<T>
+    where
+        T: Send + 'a
+    {
+        let policy = self.config.panic_policy;
+        policy.resolve(Output::flatten(self.run_outputs()))
+    }
+
+    /// Runs all closures and returns their results, with panics caught as values.
+    ///
+    /// A panicking closure does not affect the results of the others. Under
+    /// [`PanicPolicy::Abort`] a panic still aborts the process.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .add(|| 1)
+    ///     .add(|| panic!("oops"))
+    ///     .add(|| 3)
+    ///     .run_catching();
+    /// assert_eq!(*v[0].as_ref().unwrap(), 1);
+    /// assert!(v[1].is_err());
+    /// assert_eq!(*v[2].as_ref().unwrap(), 3);
+    /// ```
+    pub fn run_catching(self) -> Vec<std::thread::Result<T>>
+    where
+        T: Send + 'a
+    {
+        let outputs = self.run_outputs().into_iter().map(|output| match output {
+            // Keep a place for skipped closures, so results stay in `add` order
+            Output::Skipped => {
+                let payload: Box<dyn std::any::Any + Send> =
+                    Box::new("closure was cancelled before it ran");
+                Output::One(Err(payload))
+            }
+            output => output,
+        });
+        Output::flatten(outputs.collect())
+    }
+
+    /// Runs all closures and returns what each of them ran into, in the order
+    /// the closures were added.
+    ///
+    /// Besides the result, or the caught panic, each [`Outcome`] tells which
+    /// closure it came from, how long the closure took and which thread ran
+    /// it. Closures from [`add_producer`](Parallel::add_producer) have an
+    /// outcome per value, and cancelled closures have none.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .add(|| 1)
+    ///     .add(|| panic!("oops"))
+    ///     .run_outcomes();
+    /// assert_eq!(*v[0].result.as_ref().unwrap(), 1);
+    /// assert!(v[1].result.is_err());
+    /// assert_eq!(v[1].index, 1);
+    /// assert_eq!(v[1].thread.id(), std::thread::current().id());
+    /// ```
+    pub fn run_outcomes(self) -> Vec<Outcome<T>>
+    where
+        T: Send + 'a
+    {
+        // The time and thread of each closure, filled in as they finish
+        let meta: Arc<Vec<_>> = Arc::new((0..self.closures.len()).map(|_| Mutex::new(None)).collect());
+        let m = meta.clone();
+        let outputs = self
+            .wrap(move |i, run| {
+                let start = Instant::now();
+                let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run));
+                *m[i].lock().unwrap() = Some((start.elapsed(), std::thread::current()));
+                if let Err(err) = res {
+                    std::panic::resume_unwind(err);
+                }
+            })
+            .run_outputs();
+
+        let mut outcomes = Vec::with_capacity(outputs.len());
+        for (index, output) in outputs.into_iter().enumerate() {
+            let (duration, thread) = match meta[index].lock().unwrap().take() {
+                Some(meta) => meta,
+                None => continue,
+            };
+            let results = Output::flatten(vec![output]);
+            outcomes.extend(results.into_iter().map(|result| Outcome {
+                result,
+                index,
+                duration,
+                thread: thread.clone(),
+            }));
+        }
+        outcomes
+    }
+
+    /// Runs all closures on threads of the caller's scope `s`, and returns
+    /// their results in the order the closures were added.
+    ///
+    /// The threads belong to `s` like any other thread spawned in it, but this
+    /// still waits for every closure before returning. No closure runs on the
+    /// calling thread.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let data = vec![1, 2, 3];
+    /// std::thread::scope(|s| {
+    ///     let other = s.spawn(|| data.len());
+    ///     let v = Parallel::new().each(&data, |x| x * 2).run_in_scope(s);
+    ///     assert_eq!(v, [2, 4, 6]);
+    ///     assert_eq!(other.join().unwrap(), 3);
+    /// });
+    /// ```
+    pub fn run_in_scope<'scope, 'env>(self, s: &'scope std::thread::Scope<'scope, 'env>) -> Vec<T>
+    where
+        'a: 'scope,
+        T: Send + 'a
+    {
+        let Parallel { closures, config } = self;
+        let len = closures.len();
+        let policy = config.panic_policy;
+        let runner = Runner::new(&config, len);
+        let slots = if config.is_sequential() {
+            config.run_sequential(&runner, closures, None)
+        } else {
+            let spawn = |n, job: jobs::TaskJob<'a>| {
+                let builder = config.thread_builder(n);
+                jobs::spawn_or_run(|job| builder.spawn_scoped(s, job).map(drop), job)
+            };
+            let mut jobs = JobQueue::start(closures, &config, &runner, &spawn, true);
+
+            let mut slots: Vec<_> = (0..len).map(|_| None).collect();
+            while let Some((i, output)) = jobs.recv(None) {
+                slots[i] = Some(output);
+            }
+            slots
+        };
+        let outputs = slots.into_iter().map(Option::unwrap).collect();
+        policy.resolve(Output::flatten(outputs))
+    }
+
+    /// Runs all closures and returns their outputs, in `add` order.
+    fn run_outputs(mut self) -> Vec<Output<T>>
+    where
+        T: Send + 'a
+    {
+        let runner = Runner::new(&self.config, self.closures.len());
+        if self.config.seed.is_some() {
+            // Let the seed decide when the last closure runs too
+            let (outputs, _) = self.execute(&runner, || ());
+            return outputs;
+        }
+        let work = match self.closures.pop() {
+            None => return Vec::new(),
+            Some(task) => task.work,
+        };
+        let index = self.closures.len();
+        let (mut outputs, r) = self.execute(&runner, || runner.run(index, work));
+        outputs.push(r.unwrap_or_else(|err| Output::One(Err(err))));
+        outputs
+    }
+
+    /// Runs all closures and returns their results as an array of `N`.
+    ///
+    /// The closures always run; if they didn't produce exactly `N` results,
+    /// the results are handed back in `Err` instead.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let [a, b] = Parallel::new().add(|| 1).add(|| 2).run_array().unwrap();
+    /// assert_eq!((a, b), (1, 2));
+    ///
+    /// let err = Parallel::new().add(|| 1).run_array::<2>();
+    /// assert_eq!(err, Err(vec![1]));
+    /// ```
+    pub fn run_array<const N: usize>(self) -> Result<[T; N], Vec<T>>
+    where
+        T: Send + 'a
+    {
+        <[T; N]>::try_from(self.run())
+    }
+
+    /// Runs all closures and collects their results into any container.
+    ///
+    /// Results are fed to the container in the order the closures were added.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::collections::HashSet;
+    ///
+    /// let set: HashSet<_> = Parallel::new()
+    ///     .each([1, 2, 2, 3], |i| i)
+    ///     .run_collect();
+    /// assert_eq!(set.len(), 3);
+    /// ```
+    pub fn run_collect<C>(self) -> C
+    where
+        C: FromIterator<T>,
+        T: Send + 'a
+    {
+        self.run().into_iter().collect()
+    }
+
+    /// Runs all closures and returns their results paired with how long each
+    /// closure took.
+    ///
+    /// Times are wall-clock and measured on the thread running the closure.
+    /// Values from [`add_producer`](Parallel::add_producer) are paired with the
+    /// time since their producer started.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let v = Parallel::new()
+    ///     .add(|| 1)
+    ///     .add(|| {
+    ///         thread::sleep(Duration::from_millis(50));
+    ///         2
+    ///     })
+    ///     .run_timed();
+    /// assert_eq!(v[1].0, 2);
+    /// assert!(v[1].1 >= Duration::from_millis(50));
+    /// ```
+    pub fn run_timed(self) -> Vec<(T, Duration)>
+    where
+        T: Send + 'a
+    {
+        let Parallel { closures, config } = self;
+        Parallel {
+            closures: closures.into_iter().map(Task::timed).collect(),
+            config,
+        }
+        .run()
+    }
+
+    /// Runs all closures on their own threads and hands `f` a receiver that
+    /// yields each result as soon as its closure finishes.
+    ///
+    /// Results arrive in completion order. The receiver is disconnected once
+    /// every closure is done, and all threads are joined before this returns.
+    /// It is always a `std::sync::mpsc` receiver, even with the
+    /// `crossbeam-channel` feature.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let sum = Parallel::new()
+    ///     .each(1..=4, |i| i * i)
+    ///     .run_streaming(|results| results.iter().sum::<i32>());
+    /// assert_eq!(sum, 30);
+    /// ```
+    pub fn run_streaming<F, R>(self, f: F) -> R
+    where
+        F: FnOnce(mpsc::Receiver<T>) -> R,
+        T: Send + 'a
+    {
+        let (sender, receiver) = mpsc::channel();
+        let mut closures = Tasks::new();
+        for task in self.closures.into_iter() {
+            let sender = sender.clone();
+            closures.push(task.map(move |t| {
+                // The receiver may already have been dropped by `f`
+                let _ = sender.send(t);
+            }));
+        }
+        drop(sender);
+
+        let (_, r) = Parallel { closures, config: self.config }.finish(|| f(receiver));
+        r
+    }
+
+    /// Runs all closures and splits their results into those matching `f` and
+    /// the rest.
+    ///
+    /// Results are sorted into the two buckets on the calling thread as they
+    /// arrive, so each bucket is in completion order.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let (mut even, mut odd) = Parallel::new()
+    ///     .each(0..6, |i| i)
+    ///     .run_partition(|i| i % 2 == 0);
+    /// even.sort();
+    /// odd.sort();
+    /// assert_eq!((even, odd), (vec![0, 2, 4], vec![1, 3, 5]));
+    /// ```
+    pub fn run_partition<F>(self, f: F) -> (Vec<T>, Vec<T>)
+    where
+        F: FnMut(&T) -> bool,
+        T: Send + 'a
+    {
+        self.run_streaming(|results| results.into_iter().partition(f))
+    }
+
+    /// Runs all closures and returns the first result, then stops the batch.
+    ///
+    /// Once a closure finishes, closures that haven't started are skipped, and
+    /// [`is_cancelled`] tells the running ones to stop. This returns once they
+    /// have. Closures that panic are ignored, unless every closure panicked; an
+    /// empty batch gives `None`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let fastest = Parallel::new()
+    ///     .add(|| {
+    ///         thread::sleep(Duration::from_millis(200));
+    ///         "slow"
+    ///     })
+    ///     .add(|| "fast")
+    ///     .race();
+    /// assert_eq!(fastest, Some("fast"));
+    /// ```
+    pub fn race(self) -> Option<T>
+    where
+        T: Send + 'a
+    {
+        self.find_map_any(Some)
+    }
+
+    /// Runs all closures and combines their results with `f`, or returns
+    /// `None` for an empty batch.
+    ///
+    /// Results are combined on the threads that produced them as soon as they
+    /// finish, in no particular order, so `f` should be associative and
+    /// commutative. No `Vec` of results is collected.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let max = Parallel::new().each(vec![3, 9, 4], |i| i).reduce(i32::max);
+    /// assert_eq!(max, Some(9));
+    /// ```
+    pub fn reduce<F>(self, f: F) -> Option<T>
+    where
+        F: Fn(T, T) -> T + Send + Sync + 'a,
+        T: Send + 'a
+    {
+        let acc = Arc::new(Mutex::new(None));
+        let slot = acc.clone();
+        let f = Arc::new(f);
+        self.map(move |mut t| loop {
+            // Either leave `t` for the next result or merge in the one left
+            let mut guard = slot.lock().unwrap();
+            match guard.take() {
+                None => {
+                    *guard = Some(t);
+                    return;
+                }
+                Some(other) => {
+                    drop(guard);
+                    t = f(other, t);
+                }
+            }
+        })
+        .run();
+        let t = acc.lock().unwrap().take();
+        t
+    }
+
+    /// Runs all closures and adds up their results.
+    ///
+    /// Built on [`reduce`](Parallel::reduce); an empty batch sums to zero.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let words = Parallel::new()
+    ///     .each(vec!["a b", "c", "d e f"], |s| s.split(' ').count())
+    ///     .sum();
+    /// assert_eq!(words, 6);
+    /// ```
+    pub fn sum(self) -> T
+    where
+        T: std::iter::Sum + Send + 'a
+    {
+        self.reduce(|a, b| std::iter::once(a).chain(std::iter::once(b)).sum())
+            .unwrap_or_else(|| std::iter::empty().sum())
+    }
+
+    /// Runs all closures and multiplies their results.
+    ///
+    /// Built on [`reduce`](Parallel::reduce); an empty batch gives one.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// assert_eq!(Parallel::new().each(1..=5, |i| i).product(), 120);
+    /// ```
+    pub fn product(self) -> T
+    where
+        T: std::iter::Product + Send + 'a
+    {
+        self.reduce(|a, b| std::iter::once(a).chain(std::iter::once(b)).product())
+            .unwrap_or_else(|| std::iter::empty().product())
+    }
+
+    /// Runs all closures and combines their results with `op` on the calling
+    /// thread, each as soon as it arrives.
+    ///
+    /// Unlike [`reduce`](Parallel::reduce), `op` needn't be `Send` or `Sync`,
+    /// and results are combined in completion order. Either way no `Vec` of
+    /// results is collected.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::rc::Rc;
+    ///
+    /// let seen = Rc::new(());
+    /// let sum = Parallel::new()
+    ///     .each(1..=4, |i| i)
+    ///     .run_reduce_streaming(|a, b| {
+    ///         let _ = &seen;
+    ///         a + b
+    ///     });
+    /// assert_eq!(sum, Some(10));
+    /// ```
+    pub fn run_reduce_streaming<F>(self, op: F) -> Option<T>
+    where
+        F: FnMut(T, T) -> T,
+        T: Send + 'a
+    {
+        self.run_streaming(|results| results.into_iter().reduce(op))
+    }
+
+    /// Runs all closures, folds their results into accumulators made by
+    /// `init`, and merges the accumulators with `combine`.
+    ///
+    /// A finished closure folds its result into an idle accumulator on its own
+    /// thread, so there are only about as many accumulators as closures running
+    /// at once. They are combined on the calling thread at the end, in no
+    /// particular order.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let words = Parallel::new()
+    ///     .max_threads(2)
+    ///     .each(vec!["a b", "c", "d e f"], |s| s.split(' ').count())
+    ///     .fold(|| 0, |acc, n| acc + n, |a, b| a + b);
+    /// assert_eq!(words, 6);
+    /// ```
+    pub fn fold<A, I, F, C>(self, init: I, fold: F, combine: C) -> A
+    where
+        I: Fn() -> A + Send + Sync + 'a,
+        F: Fn(A, T) -> A + Send + Sync + 'a,
+        C: FnMut(A, A) -> A,
+        A: Send + 'a,
+        T: Send + 'a
+    {
+        let idle = Arc::new(Mutex::new(Vec::new()));
+        let init = Arc::new(init);
+        let fold = Arc::new(fold);
+        let (pool, make) = (idle.clone(), init.clone());
+        self.map(move |t| {
+            let acc = pool.lock().unwrap().pop().unwrap_or_else(|| make());
+            let acc = fold(acc, t);
+            pool.lock().unwrap().push(acc);
+        })
+        .run();
+        let accs = std::mem::take(&mut *idle.lock().unwrap());
+        accs.into_iter().reduce(combine).unwrap_or_else(|| init())
+    }
+
+    /// Runs all closures until `f` maps one of their results to `Some`, then
+    /// stops the batch and returns it.
+    ///
+    /// If no result matches, panics are resumed under the panic policy.
+    fn find_map_any<U, F>(mut self, mut f: F) -> Option<U>
+    where
+        F: FnMut(T) -> Option<U>,
+        T: Send + 'a
+    {
+        let cancel = Cancel::default();
+        self.config.cancel = Some(cancel.clone());
+        let policy = self.config.panic_policy;
+
+        let (sender, receiver) = channel::channel();
+        let parallel = self.map(move |t| {
+            // Results sent after a match are never received
+            let _ = sender.send(t);
+        });
+        let runner = Runner::new(&parallel.config, parallel.closures.len());
+        let (outputs, found) = parallel.execute(&runner, || {
+            let found = receiver.iter().find_map(&mut f);
+            cancel.cancel();
+            found
+        });
+
+        match found {
+            Ok(Some(u)) => Some(u),
+            Ok(None) => {
+                policy.resolve(Output::flatten(outputs));
+                None
+            }
+            Err(err) => std::panic::resume_unwind(err),
+        }
+    }
+
+    /// Runs all closures on their own threads, and `f` on the calling thread.
+    ///
+    /// Results of the closures are returned in the order they were added,
+    /// whatever order they finish in, together with the result of `f`. `f`
+    /// starts once every closure has been handed to a thread, or queued under
+    /// [`max_threads`](Parallel::max_threads), and runs alongside them; this
+    /// returns once `f` and all closures are done.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let (v, r) = Parallel::new()
+    ///     .each(0..4, |i| i * 10)
+    ///     .finish(|| "main");
+    /// assert_eq!(v, [0, 10, 20, 30]);
+    /// assert_eq!(r, "main");
+    /// ```
+    pub fn finish<F, R>(self, f: F) -> (Vec<T>, R)
+    where
+        F: FnOnce() -> R,
+        T: Send + 'a
+    {
+        let policy = self.config.panic_policy;
+        let runner = Runner::new(&self.config, self.closures.len());
+        let (outputs, res) = self.execute(&runner, f);
+        let results = policy.resolve(Output::flatten(outputs));
+
+        // If the main closure panicked, resume its panic
+        match res {
+            Ok(r) => (results, r),
+            Err(err) => std::panic::resume_unwind(err)
+        }
+    }
+
+    /// Runs all closures, and `f` on each of `roots` and on every item pushed to
+    /// the [`WorkQueue`] it is given, until no items are left.
+    ///
+    /// The closures and items share one queue, served by
+    /// [`max_threads`](Parallel::max_threads) threads, or one per available
+    /// CPU, with the calling thread as one of them. A pushed item is picked
+    /// up by the next free thread, and the run ends once no item is queued or
+    /// running. Results come in the order the closures were added, then the
+    /// order the items were pushed, roots first.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// // Split ranges in half until they are small, then sum them
+    /// let sums = Parallel::new().run_recursive(vec![0..100u64], |range, queue| {
+    ///     if range.end - range.start > 10 {
+    ///         let mid = (range.start + range.end) / 2;
+    ///         queue.push(range.start..mid);
+    ///         queue.push(mid..range.end);
+    ///         0
+    ///     } else {
+    ///         range.sum()
+    ///     }
+    /// });
+    /// assert_eq!(sums.iter().sum::<u64>(), 4950);
+    /// ```
+    ///
+    /// Pushed items don't wait for the item that pushed them to finish:
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::{mpsc, Mutex};
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let rx = Mutex::new(rx);
+    /// let v = Parallel::new().max_threads(2).run_recursive(vec!["parent"], |item, queue| {
+    ///     if item == "parent" {
+    ///         queue.push("child");
+    ///         // Another thread picks up the child meanwhile
+    ///         rx.lock().unwrap().recv().unwrap()
+    ///     } else {
+    ///         tx.send("from child").unwrap();
+    ///         "child"
+    ///     }
+    /// });
+    /// assert_eq!(v, ["from child", "child"]);
+    /// ```
+    pub fn run_recursive<A, I, F>(self, roots: I, f: F) -> Vec<T>
+    where
+        I: IntoIterator<Item = A>,
+        F: FnOnce(A, &WorkQueue<A>) -> T + Clone + Send + 'a,
+        A: Send + 'a,
+        T: Send + 'a
+    {
+        let Parallel { closures, config } = self;
+        let policy = config.panic_policy;
+        let outputs = recursive::run(closures, config, roots, f);
+        policy.resolve(Output::flatten(outputs))
+    }
+
+    /// Like [`finish`](Parallel::finish), with the result of `f` first.
+    ///
+    /// The ordering guarantees are those of `finish`: results in `add` order,
+    /// and `f` started after all closures have been handed out.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let (r, v) = Parallel::new()
+    ///     .each(0..4, |i| i * 10)
+    ///     .finish_ordered(|| "main");
+    /// assert_eq!(r, "main");
+    /// assert_eq!(v, [0, 10, 20, 30]);
+    /// ```
+    pub fn finish_ordered<F, R>(self, f: F) -> (R, Vec<T>)
+    where
+        F: FnOnce() -> R,
+        T: Send + 'a
+    {
+        let (results, r) = self.finish(f);
+        (r, results)
+    }
+
+    /// Like [`finish`](Parallel::finish), but `f` gets a [`BatchScope`] to add
+    /// more closures while the batch runs.
+    ///
+    /// Each added closure gets a thread of its own right away, and its results
+    /// come after those of the closures added before the run, in the order
+    /// they were added.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let (v, ()) = Parallel::new()
+    ///     .add(|| 1)
+    ///     .finish_scoped(|scope| {
+    ///         for i in 2..=3 {
+    ///             scope.spawn(move || i);
+    ///         }
+    ///     });
+    /// assert_eq!(v, [1, 2, 3]);
+    /// ```
+    pub fn finish_scoped<F, R>(self, f: F) -> (Vec<T>, R)
+    where
+        F: FnOnce(&BatchScope<'_, 'a, T>) -> R,
+        T: Send + 'a
+    {
+        let policy = self.config.panic_policy;
+        let config = self.config.clone();
+        let len = self.closures.len();
+        let runner = Runner::new(&self.config, len);
+        let (mut outputs, res) = self.execute(&runner, || {
+            std::thread::scope(|s| {
+                let (config, runner) = (&config, &runner);
+                let handles = std::cell::RefCell::new(Vec::new());
+                let spawn = |task: Task<'a, T>| {
+                    let i = len + handles.borrow().len();
+                    runner.add_task();
+                    if config.is_sequential() {
+                        handles.borrow_mut().push(Err(runner.run(i, task.work)));
+                        return;
+                    }
+
+                    // Keep the task reachable in case the OS refuses the thread
+                    let cell = Arc::new(Mutex::new(Some(task)));
+                    let theirs = cell.clone();
+                    let spawned = config.thread_builder(i).spawn_scoped(s, move || {
+                        let task = theirs.lock().unwrap().take().unwrap();
+                        let mut current = ThreadSettings::default();
+                        current.switch(ThreadSettings {
+                            priority: task.thread_priority.or(config.thread_priority),
+                            core: task.core.or_else(|| config.core(i)),
+                        });
+                        config.init_worker(i);
+                        let output = runner.run(i, task.work);
+                        config.exit_worker(i);
+                        output
+                    });
+                    let handle = spawned.map_err(|_| {
+                        let task = cell.lock().unwrap().take().unwrap();
+                        runner.run(i, task.work)
+                    });
+                    handles.borrow_mut().push(handle);
+                };
+                let r = f(&BatchScope { spawn: &spawn });
+
+                let added: Vec<_> = handles
+                    .into_inner()
+                    .into_iter()
+                    .map(|handle| match handle {
+                        Ok(h) => h.join().unwrap_or_else(|err| std::panic::resume_unwind(err)),
+                        Err(output) => output,
+                    })
+                    .collect();
+                (added, r)
+            })
+        });
+
+        // If the main closure panicked, resume its panic
+        match res {
+            Ok((added, r)) => {
+                outputs.extend(added);
+                (policy.resolve(Output::flatten(outputs)), r)
+            }
+            Err(err) => std::panic::resume_unwind(err),
+        }
+    }
+
+    /// Runs the closures on scoped threads and `f` on the calling thread,
+    /// catching every panic.
+    fn execute<F, R>(self, runner: &Runner<'a>, f: F) -> (Vec<Output<T>>, std::thread::Result<R>)
+    where
+        F: FnOnce() -> R,
+        T: Send + 'a
+    {
+        if self.config.is_sequential() {
+            let slots = self.config.run_sequential(runner, self.closures, None);
+            let outputs = slots.into_iter().map(Option::unwrap).collect();
+            return (outputs, std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)));
+        }
+        if let Some(spawner) = self.config.spawner.clone() {
+            return self.execute_on(spawner, runner, f);
+        }
+
+        let Parallel { closures, config } = self;
+        let len = closures.len();
+        let workers = config.max_threads().map_or(len, |n| n.min(len));
+        let mut tasks = task::start_order(closures);
+        let cells: Vec<_> = if workers == len {
+            tasks.by_ref().map(|task| Mutex::new(Some(task))).collect()
+        } else {
+            Vec::new()
+        };
+        let queue = Mutex::new(tasks);
+        let slots: Vec<_> = (0..len).map(|_| Mutex::new(None)).collect();
+        let gate = if config.start_together { Some(StartGate::default()) } else { None };
+
+        // Set up a guard that aborts on panic, unless unwinding is allowed
+        let guard = if config.no_abort { None } else { Some(NoPanic) };
+
+        // Scoped threads may borrow anything that outlives `'a`, and the scope
+        // joins every one of them before returning
+        let (res, hook_panic) = std::thread::scope(|s| {
+            let (config, queue, slots, gate) = (&config, &queue, &slots[..], gate.as_ref());
+            let mut handles = Vec::new();
+            let opening = Opening(gate);
+            for w in 0..workers {
+                if w > 0 {
+                    config.stagger();
+                }
+                let handle = match cells.get(w) {
+                    // One thread per closure
+                    Some(cell) => {
+                        let next = StartGate::hold(gate, move || cell.lock().unwrap().take());
+                        config.spawn_worker(s, runner, slots, w, next)
+                    }
+                    // A fixed set of threads taking closures from a shared queue
+                    None => {
+                        let next = StartGate::hold(gate, move || queue.lock().unwrap().next());
+                        config.spawn_worker(s, runner, slots, w, next)
+                    }
+                };
+                match handle {
+                    Some(handle) => handles.push(handle),
+                    None => break,
+                }
+            }
+
+            if let Some(gate) = gate {
+                gate.open(handles.len());
+            }
+            drop(opening);
+            if workers > 0 {
+                config.stagger();
+            }
+
+            if handles.len() < workers {
+                // Out of threads, so run what is left on this one
+                let next = || {
+                    let cell = cells.iter().find_map(|cell| cell.lock().unwrap().take());
+                    cell.or_else(|| queue.lock().unwrap().next())
+                };
+                while let Some((i, task)) = next() {
+                    *slots[i].lock().unwrap() = Some(runner.run(i, task.work));
+                }
+            }
+
+            // Run the main closure on the main thread
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+
+            // Closures can't unwind out of a worker, but its hooks can
+            let mut hook_panic = None;
+            for h in handles {
+                if let Err(err) = h.join() {
+                    hook_panic.get_or_insert(err);
+                }
+            }
+            (res, hook_panic)
+        });
+        drop(guard);
+        if let Some(err) = hook_panic {
+            std::panic::resume_unwind(err);
+        }
+
+        // Every task has filled its slot, in `add` order
+        let results = slots
+            .into_iter()
+            .map(|slot| slot.into_inner().unwrap().unwrap())
+            .collect();
+        (results, res)
+    }
+
+    /// Runs the closures as jobs of `spawner` and `f` on the calling thread,
+    /// catching every panic.
+    fn execute_on<F, R>(
+        self,
+        spawner: Arc<dyn Spawn>,
+        runner: &Runner<'a>,
+        f: F,
+    ) -> (Vec<Output<T>>, std::thread::Result<R>)
+    where
+        F: FnOnce() -> R,
+        T: Send + 'a
+    {
+        let Parallel { closures, config } = self;
+        let len = closures.len();
+        let scope = JobScope::new(spawner);
+        let spawn = |_, job| scope.spawn(job);
+        let mut jobs = JobQueue::start(closures, &config, runner, &spawn, false);
+        if len > 0 {
+            config.stagger();
+        }
+
+        // Run the main closure on the main thread
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+
+        let mut slots: Vec<_> = (0..len).map(|_| None).collect();
+        while let Some((i, output)) = jobs.recv(None) {
+            slots[i] = Some(output);
+        }
+        let outputs = slots.into_iter().map(Option::unwrap).collect();
+        (outputs, res)
+    }
+}
+
+impl Parallel<'_, ()> {
+    /// Runs `a` on a new thread and `b` on the calling thread, and returns
+    /// both results.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let (n, s) = Parallel::join2(|| 1 + 1, || "two".to_string());
+    /// assert_eq!((n, s.as_str()), (2, "two"));
+    /// ```
+    pub fn join2<A, B, FA, FB>(a: FA, b: FB) -> (A, B)
+    where
+        FA: FnOnce() -> A + Send,
+        FB: FnOnce() -> B,
+        A: Send
+    {
+        let mut ra = None;
+        let (_, rb) = Parallel::new().add(|| ra = Some(a())).finish(b);
+        (ra.unwrap(), rb)
+    }
+
+    /// Runs `a` and `b` on new threads and `c` on the calling thread, and
+    /// returns all three results.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let (n, s, b) = Parallel::join3(|| 1, || "two", || true);
+    /// assert_eq!((n, s, b), (1, "two", true));
+    /// ```
+    pub fn join3<A, B, C, FA, FB, FC>(a: FA, b: FB, c: FC) -> (A, B, C)
+    where
+        FA: FnOnce() -> A + Send,
+        FB: FnOnce() -> B + Send,
+        FC: FnOnce() -> C,
+        A: Send,
+        B: Send
+    {
+        let (mut ra, mut rb) = (None, None);
+        let (_, rc) = Parallel::new()
+            .add(|| ra = Some(a()))
+            .add(|| rb = Some(b()))
+            .finish(c);
+        (ra.unwrap(), rb.unwrap(), rc)
+    }
+}
+
+impl<'a, T: Send + 'a, E: Send + 'a> Parallel<'a, Result<T, E>> {
+    /// Adds a fallible closure that is retried on its thread up to `retries`
+    /// times before its error is reported.
+    ///
+    /// The first retry waits `backoff`, and each further retry waits twice as
+    /// long as the last. Retrying stops early once the batch is cancelled.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::time::Duration;
+    ///
+    /// let mut attempts = 0;
+    /// let v = Parallel::new()
+    ///     .add_with_retry(3, Duration::from_millis(1), || {
+    ///         attempts += 1;
+    ///         if attempts < 3 { Err("flaky") } else { Ok(attempts) }
+    ///     })
+    ///     .run();
+    /// assert_eq!(v, [Ok(3)]);
+    /// ```
+    pub fn add_with_retry<F>(
+        self,
+        retries: usize,
+        backoff: Duration,
+        mut f: F,
+    ) -> Parallel<'a, Result<T, E>>
+    where
+        F: FnMut() -> Result<T, E> + Send + 'a
+    {
+        self.add(move || {
+            let mut delay = backoff;
+            let mut res = f();
+            for _ in 0..retries {
+                if res.is_ok() || is_cancelled() {
+                    break;
+                }
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                res = f();
+            }
+            res
+        })
+    }
+
+    /// Runs all closures and returns their results, or one [`anyhow::Error`]
+    /// for all the errors they returned.
+    ///
+    /// Every closure runs to completion. The first error in `add` order is the
+    /// source of the error, so it can still be downcast, and the context names
+    /// each closure that failed along with its error.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let err = Parallel::new()
+    ///     .add(|| Ok(1))
+    ///     .add(|| Err(std::fmt::Error))
+    ///     .add(|| Err(std::fmt::Error))
+    ///     .try_run_anyhow()
+    ///     .unwrap_err();
+    /// assert!(err.to_string().starts_with("closure 1 failed; closure 2 also failed"));
+    /// assert!(err.downcast_ref::<std::fmt::Error>().is_some());
+    /// ```
+    #[cfg(feature = "anyhow")]
+    pub fn try_run_anyhow(self) -> anyhow::Result<Vec<T>>
+    where
+        E: Into<anyhow::Error>
+    {
+        use std::fmt::Write;
+
+        let mut values = Vec::new();
+        let mut errors = Vec::new();
+        for (i, res) in self.run().into_iter().enumerate() {
+            match res {
+                Ok(t) => values.push(t),
+                Err(err) => errors.push((i, err.into())),
+            }
+        }
+        let mut errors = errors.into_iter();
+        let (i, first): (usize, anyhow::Error) = match errors.next() {
+            None => return Ok(values),
+            Some(first) => first,
+        };
+        let mut context = format!("closure {} failed", i);
+        for (i, err) in errors {
+            let _ = write!(context, "; closure {} also failed: {:#}", i, err);
+        }
+        Err(first.context(context))
+    }
+
+    /// Runs all closures and returns their results, or stops the batch as soon
+    /// as one returns an error.
+    ///
+    /// After an error, closures that haven't started are skipped and
+    /// [`is_cancelled`] tells the running ones to stop. Of the errors returned
+    /// by then, the first in `add` order is returned.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let ok: Result<Vec<i32>, String> = Parallel::new().each(0..4, Ok).try_run();
+    /// assert_eq!(ok, Ok(vec![0, 1, 2, 3]));
+    ///
+    /// let err = Parallel::new()
+    ///     .max_threads(1)
+    ///     .each(0..100, |i| if i == 3 { Err(i) } else { Ok(i) })
+    ///     .try_run();
+    /// assert_eq!(err, Err(3));
+    /// ```
+    pub fn try_run(mut self) -> Result<Vec<T>, E> {
+        let cancel = Cancel::default();
+        self.config.cancel = Some(cancel.clone());
+        self.map(move |res| {
+            if res.is_err() {
+                cancel.cancel();
+            }
+            res
+        })
+        .run()
+        .into_iter()
+        .collect()
+    }
+}
+
+impl Parallel<'_, bool> {
+    /// Runs the closures until one returns `true`, then stops the batch.
+    ///
+    /// See [`Parallel::race`] for how the batch is stopped.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// assert!(Parallel::new().each(0..8, |i| i == 5).any());
+    /// assert!(!Parallel::new().each(0..8, |i| i > 10).any());
+    /// ```
+    pub fn any(self) -> bool {
+        self.find_map_any(|b| if b { Some(()) } else { None }).is_some()
+    }
+
+    /// Runs the closures until one returns `false`, then stops the batch.
+    ///
+    /// See [`Parallel::race`] for how the batch is stopped.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// assert!(Parallel::new().each(0..8, |i| i < 10).all());
+    /// assert!(!Parallel::new().each(0..8, |i| i != 5).all());
+    /// ```
+    pub fn all(self) -> bool {
+        self.find_map_any(|b| if b { None } else { Some(()) }).is_none()
+    }
+}
+
+impl<'a, A: Send + 'a, U: Send + 'a> Parallel<'a, (A, U)> {
+    /// Like [`Parallel::each`], but each result is paired with the item it
+    /// came from, which `f` gets by reference.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().each_keyed(vec!["a", "bb"], |s| s.len()).run();
+    /// assert_eq!(v, [("a", 1), ("bb", 2)]);
+    /// ```
+    pub fn each_keyed<I, F>(self, iter: I, f: F) -> Parallel<'a, (A, U)>
+    where
+        I: IntoIterator<Item = A>,
+        F: FnOnce(&A) -> U + Clone + Send + 'a
+    {
+        self.each(iter, move |a| {
+            let u = f(&a);
+            (a, u)
+        })
+    }
+
+    /// Adds a closure whose result is paired with `key`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let m = Parallel::new()
+    ///     .add_named("users", || 3)
+    ///     .add_named("posts", || 12)
+    ///     .run_map();
+    /// assert_eq!(m["posts"], 12);
+    /// ```
+    pub fn add_named<F>(self, key: A, f: F) -> Parallel<'a, (A, U)>
+    where
+        F: FnOnce() -> U + Send + 'a
+    {
+        self.add(move || (key, f()))
+    }
+
+    /// Runs all closures and returns their results by key.
+    ///
+    /// If two closures share a key, the one added last wins.
+    pub fn run_map(self) -> std::collections::HashMap<A, U>
+    where
+        A: Eq + std::hash::Hash
+    {
+        self.run_collect()
+    }
+}
+
+impl<'a, T: Send + 'a> Parallel<'a, Vec<T>> {
+    /// Splits `iter` into `n` contiguous chunks of about equal length, and adds
+    /// one closure per chunk that maps its items through `f`.
+    ///
+    /// Each chunk yields a `Vec` of results, so a batch of cheap items doesn't
+    /// need one thread per item. Fewer than `n` closures are added if there
+    /// aren't enough items, and `n` is at least one.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().each_chunked(3, 0..8, |i| i * 10).run();
+    /// assert_eq!(v, [vec![0, 10, 20], vec![30, 40, 50], vec![60, 70]]);
+    /// ```
+    pub fn each_chunked<A, I, F>(self, n: usize, iter: I, f: F) -> Parallel<'a, Vec<T>>
+    where
+        I: IntoIterator<Item = A>,
+        F: FnMut(A) -> T + Clone + Send + 'a,
+        A: Send + 'a
+    {
+        let mut items: Vec<A> = iter.into_iter().collect();
+        let n = n.max(1);
+        let (size, longer) = (items.len() / n, items.len() % n);
+
+        // Split off chunks from the back, so no item is moved twice
+        let mut chunks = Vec::with_capacity(n);
+        for k in (0..n).rev() {
+            let start = k * size + k.min(longer);
+            if start < items.len() {
+                chunks.push(items.split_off(start));
+            }
+        }
+
+        chunks.reverse();
+        self.add_chunks(chunks, f)
+    }
+
+    /// Like [`each_chunked`](Parallel::each_chunked), with one chunk per
+    /// available CPU.
+    ///
+    /// The number of chunks is `EASY_PARALLEL_THREADS` if set, and otherwise
+    /// [`std::thread::available_parallelism`].
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().each_auto(0..1000, |i| i * 2).run();
+    /// assert_eq!(v.concat(), (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+    /// ```
+    pub fn each_auto<A, I, F>(self, iter: I, f: F) -> Parallel<'a, Vec<T>>
+    where
+        I: IntoIterator<Item = A>,
+        F: FnMut(A) -> T + Clone + Send + 'a,
+        A: Send + 'a
+    {
+        self.each_chunked(default_threads(), iter, f)
+    }
+
+    /// Like [`each_chunked`](Parallel::each_chunked), but splits `iter` into
+    /// chunks of about equal total `cost` instead of equal length.
+    ///
+    /// An expensive item then gets a chunk to itself, or nearly so, instead of
+    /// holding up the cheap items sharing its chunk. Chunks are still
+    /// contiguous, so results come in the order of `iter`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new()
+    ///     .each_chunked_by_cost(2, vec![100, 1, 1, 1], |&c| c, |c| c)
+    ///     .run();
+    /// assert_eq!(v, [vec![100], vec![1, 1, 1]]);
+    /// ```
+    pub fn each_chunked_by_cost<A, I, C, F>(
+        self,
+        n: usize,
+        iter: I,
+        mut cost: C,
+        f: F,
+    ) -> Parallel<'a, Vec<T>>
+    where
+        I: IntoIterator<Item = A>,
+        C: FnMut(&A) -> u64,
+        F: FnMut(A) -> T + Clone + Send + 'a,
+        A: Send + 'a
+    {
+        let items: Vec<A> = iter.into_iter().collect();
+        let costs: Vec<u64> = items.iter().map(&mut cost).collect();
+        let total: u128 = costs.iter().map(|&c| u128::from(c)).sum();
+        let n = n.max(1);
+
+        let mut chunks = Vec::with_capacity(n);
+        let mut chunk = Vec::new();
+        let mut acc = 0;
+        for (item, c) in items.into_iter().zip(costs) {
+            acc += u128::from(c);
+            chunk.push(item);
+            // Close the chunk once the chunks so far have their share of the total
+            let k = chunks.len() + 1;
+            if k < n && acc * n as u128 >= k as u128 * total {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        self.add_chunks(chunks, f)
+    }
+
+    /// Adds one closure per chunk that maps its items through `f`.
+    fn add_chunks<A, F>(mut self, chunks: Vec<Vec<A>>, f: F) -> Parallel<'a, Vec<T>>
+    where
+        F: FnMut(A) -> T + Clone + Send + 'a,
+        A: Send + 'a
+    {
+        for chunk in chunks {
+            let f = f.clone();
+            let f = Box::new(move || chunk.into_iter().map(f).collect());
+            self.closures.push(Task::new(Work::Call(f)));
+        }
+        self
+    }
+}
+
+impl<T: Send + 'static> Parallel<'static, T> {
+    /// Runs all closures, waiting at most `timeout` for them to finish.
+    ///
+    /// Returns the results of the closures that finished in time; the others
+    /// keep running in the background, or never start if they were still
+    /// queued by [`Parallel::max_threads`]. No closure runs on the calling
+    /// thread, so the closures must be `'static`.
+    ///
+    /// The exception is [`Parallel::sequential`], where the closures run on the
+    /// calling thread one after another. The deadline is then only checked
+    /// between them: a closure that overruns it still finishes, and the ones
+    /// after it are left unfinished without running.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let partial = Parallel::new()
+    ///     .add(|| 1)
+    ///     .add(|| {
+    ///         thread::sleep(Duration::from_secs(10));
+    ///         2
+    ///     })
+    ///     .add(|| 3)
+    ///     .run_timeout(Duration::from_millis(500));
+    /// assert_eq!(partial.results, [1, 3]);
+    /// assert_eq!(partial.unfinished, [1]);
+    ///
+    /// let partial = Parallel::new()
+    ///     .sequential()
+    ///     .add(|| 1)
+    ///     .add(|| {
+    ///         thread::sleep(Duration::from_millis(600));
+    ///         2
+    ///     })
+    ///     .add(|| 3)
+    ///     .run_timeout(Duration::from_millis(500));
+    /// assert_eq!(partial.results, [1, 2]);
+    /// assert_eq!(partial.unfinished, [2]);
+    /// ```
+    pub fn run_timeout(self, timeout: Duration) -> Partial<T> {
+        self.run_until(Instant::now() + timeout)
+    }
+
+    /// Runs all closures, waiting for them until `deadline` at the latest.
+    ///
+    /// Like [`Parallel::run_timeout`], but with an absolute point in time.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let deadline = Instant::now() + Duration::from_secs(5);
+    /// let partial = Parallel::new().each(0..3, |i| i * 2).run_until(deadline);
+    /// assert_eq!(partial.results, [0, 2, 4]);
+    /// assert!(partial.unfinished.is_empty());
+    /// ```
+    pub fn run_until(self, deadline: Instant) -> Partial<T> {
+        let Parallel { closures, config } = self;
+        let len = closures.len();
+        let policy = config.panic_policy;
+        let runner = Runner::new(&config, len);
+        let slots = if config.is_sequential() {
+            config.run_sequential(&runner, closures, Some(deadline))
+        } else {
+            let spawner = config.spawner.clone();
+            let spawn = |n, job: jobs::TaskJob<'static>| match &spawner {
+                Some(spawner) => spawner.spawn(job),
+                None => {
+                    let builder = config.thread_builder(n);
+                    jobs::spawn_or_run(|job| builder.spawn(job).map(drop), job)
+                }
+            };
+            let own_threads = spawner.is_none();
+            let mut jobs = JobQueue::start(closures, &config, &runner, &spawn, own_threads);
+
+            let mut slots: Vec<_> = (0..len).map(|_| None).collect();
+            while let Some((i, output)) = jobs.recv(Some(deadline)) {
+                slots[i] = Some(output);
+            }
+            slots
+        };
+
+        let mut outputs = Vec::new();
+        let mut unfinished = Vec::new();
+        for (i, slot) in slots.into_iter().enumerate() {
+            match slot {
+                Some(output) => outputs.push(output),
+                None => unfinished.push(i),
+            }
+        }
+        Partial {
+            results: policy.resolve(Output::flatten(outputs)),
+            unfinished,
+        }
+    }
+
+    /// Runs the closures as jobs of `spawner` instead of on threads of their own.
+    ///
+    /// As with the default backend, the last closure added runs on the calling
+    /// thread in [`Parallel::run`], and so does the main closure of
+    /// [`Parallel::finish`].
+    /// The panic policy, [`Parallel::max_threads`] and thread priorities and
+    /// cores are honored. Cores are undone once each closure finishes, and so
+    /// are priorities where the OS allows it, which Linux doesn't for lowered
+    /// ones (see [`Parallel::thread_priority`]). Thread names and stack sizes
+    /// are up to the backend.
+    ///
+    /// ```
+    /// use easy_parallel::{Inline, Parallel};
+    ///
+    /// let v = Parallel::new().with_spawner(Inline).each(0..3, |i| i).run();
+    /// assert_eq!(v, [0, 1, 2]);
+    /// ```
+    pub fn with_spawner<S: Spawn + 'static>(mut self, spawner: S) -> Parallel<'static, T> {
+        self.config.spawner = Some(Arc::new(spawner));
+        self
+    }
+
+    /// Starts running all closures and returns a future of their results, in
+    /// the order the closures were added.
+    ///
+    /// The closures start right away, on threads of their own, so awaiting the
+    /// future never blocks the executor. Because the threads may outlive the
+    /// future, the closures must be `'static`. Panics, including one from a
+    /// hook such as [`Parallel::on_worker_init`], are resumed when the future
+    /// is polled.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// # use std::future::Future;
+    /// # use std::sync::Arc;
+    /// # use std::task::{Context, Poll, Wake};
+    /// # struct Unpark(std::thread::Thread);
+    /// # impl Wake for Unpark {
+    /// #     fn wake(self: Arc<Self>) { self.0.unpark() }
+    /// # }
+    /// # fn block_on<F: Future>(f: F) -> F::Output {
+    /// #     let mut f = Box::pin(f);
+    /// #     let waker = Arc::new(Unpark(std::thread::current())).into();
+    /// #     let mut cx = Context::from_waker(&waker);
+    /// #     loop {
+    /// #         match f.as_mut().poll(&mut cx) {
+    /// #             Poll::Ready(out) => return out,
+    /// #             Poll::Pending => std::thread::park(),
+    /// #         }
+    /// #     }
+    /// # }
+    ///
+    /// # block_on(async {
+    /// let v = Parallel::new().each(0..4, |i| i * i).run_async().await;
+    /// assert_eq!(v, [0, 1, 4, 9]);
+    /// # });
+    /// ```
+    pub fn run_async(self) -> RunFuture<T> {
+        let policy = self.config.panic_policy;
+        RunFuture::spawn(policy, move || Output::flatten(self.run_outputs()))
+    }
+
+    /// Starts running all closures and returns a handle to join them later.
+    ///
+    /// The closures start right away, on threads of their own, leaving the
+    /// calling thread free for other work until [`ParallelHandle::join`].
+    /// Because the threads may outlive the handle, the closures must be
+    /// `'static`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let handle = Parallel::new().each(0..4, |i| i * 10).spawn();
+    /// let other = 1 + 2;
+    /// assert_eq!(handle.join(), [0, 10, 20, 30]);
+    /// assert_eq!(other, 3);
+    /// ```
+    pub fn spawn(self) -> ParallelHandle<T> {
+        let policy = self.config.panic_policy;
+        ParallelHandle::spawn(policy, move || Output::flatten(self.run_outputs()))
+    }
+
+    /// Starts running all closures and returns a receiver of their results,
+    /// each with the index of its closure, along with a guard that joins the
+    /// batch.
+    ///
+    /// Results arrive in completion order, and the receiver is disconnected
+    /// once every closure is done. Dropping the guard waits for the closures
+    /// and resumes a panic under the panic policy, so keep it alive while
+    /// receiving. As with [`Parallel::spawn`], the closures must be `'static`.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let (results, guard) = Parallel::new().each(0..4, |i| i * 10).spawn_streaming();
+    /// let mut v: Vec<_> = results.iter().collect();
+    /// guard.join();
+    /// v.sort();
+    /// assert_eq!(v, [(0, 0), (1, 10), (2, 20), (3, 30)]);
+    /// ```
+    pub fn spawn_streaming(self) -> (mpsc::Receiver<(usize, T)>, JoinGuard) {
+        let (sender, receiver) = mpsc::channel();
+        let Parallel { closures, config } = self;
+        let closures = closures
+            .into_iter()
+            .enumerate()
+            .map(|(i, task)| {
+                let sender = sender.clone();
+                task.map(move |t| {
+                    // The receiver may already have been dropped
+                    let _ = sender.send((i, t));
+                })
+            })
+            .collect();
+        drop(sender);
+        let handle = Parallel { closures, config }.spawn();
+        (receiver, JoinGuard::new(handle))
+    }
+
+    /// Runs all closures on the process-wide [`ParallelPool::global`] and
+    /// returns their results, in the order the closures were added.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    ///
+    /// let v = Parallel::new().each(0..4, |i| i + 1).run_on_global();
+    /// assert_eq!(v, [1, 2, 3, 4]);
+    /// ```
+    #[cfg(feature = "global-pool")]
+    pub fn run_on_global(self) -> Vec<T> {
+        ParallelPool::global().run(self)
+    }
+}
+
+impl Parallel<'static, ()> {
+    /// Starts running all closures in the background and returns right away.
+    ///
+    /// Nothing is left to join. The panic policy still applies: with
+    /// [`PanicPolicy::Abort`] a panicking closure aborts the process, and
+    /// otherwise the panic is resumed on a background thread once the batch
+    /// is done, where it is reported by the panic hook.
+    ///
+    /// ```
+    /// use easy_parallel::Parallel;
+    /// use std::sync::mpsc;
+    ///
+    /// let (s, r) = mpsc::channel();
+    /// Parallel::new()
+    ///     .each(0..3, move |i| s.send(i).unwrap())
+    ///     .detach();
+    /// let mut v: Vec<_> = r.iter().collect();
+    /// v.sort();
+    /// assert_eq!(v, [0, 1, 2]);
+    /// ```
+    pub fn detach(self) {
+        let policy = self.config.panic_policy;
+        let run = move || {
+            policy.resolve(Output::flatten(self.run_outputs()));
+        };
+        if NO_THREADS {
+            // The panic ends the batch, as it would end its thread
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run));
+        } else {
+            std::thread::spawn(run);
+        }
+    }
+}
+
+/// Two batches run as one, returned by [`Parallel::zip`].
+#[must_use]
+pub struct Zip<'a, A, B> {
+    parallel: Parallel<'a, Side<A, B>>,
+}
+
+/// A result of either batch of a [`Zip`]
+enum Side<A, B> {
+    Left(A),
+    Right(B),
+}
+
+impl<'a, A: Send + 'a, B: Send + 'a> Zip<'a, A, B> {
+    /// Runs all closures of both batches and pairs their results.
+    pub fn run(self) -> Vec<(A, B)> {
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        for side in self.parallel.run() {
+            match side {
+                Side::Left(a) => left.push(a),
+                Side::Right(b) => right.push(b),
+            }
+        }
+        left.into_iter().zip(right).collect()
+    }
+}
+
+impl<A, B> std::fmt::Debug for Zip<'_, A, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Zip")
+            .field("len", &self.parallel.len())
+            .finish()
+    }
+}
+
+/// A [`Parallel`] whose closures share a state, returned by
+/// [`Parallel::with_state`].
+#[must_use]
+pub struct WithState<'a, T, S> {
+    parallel: Parallel<'a, T>,
+    state: Arc<S>,
+}
+
+impl<'a, T: Send + 'a, S: Send + Sync + 'a> WithState<'a, T, S> {
+    /// Adds a closure that gets the shared state.
+    pub fn add_with<F>(mut self, f: F) -> WithState<'a, T, S>
+    where
+        F: FnOnce(&S) -> T + Send + 'a
+    {
+        let state = self.state.clone();
+        self.parallel = self.parallel.add(move || f(&state));
+        self
+    }
+
+    /// Like [`Parallel::each`], but `f` also gets the shared state.
+    pub fn each_with<A, I, F>(mut self, iter: I, f: F) -> WithState<'a, T, S>
+    where
+        I: IntoIterator<Item = A>,
+        F: FnOnce(&S, A) -> T + Clone + Send + 'a,
+        A: Send + 'a
+    {
+        let state = self.state.clone();
+        self.parallel = self.parallel.each(iter, move |a| f(&state, a));
+        self
+    }
+
+    /// Returns the batch, to set it up further or run it another way.
+    pub fn into_parallel(self) -> Parallel<'a, T> {
+        self.parallel
+    }
+
+    /// Runs all closures and returns their results, like [`Parallel::run`].
+    pub fn run(self) -> Vec<T> {
+        self.parallel.run()
+    }
+}
+
+impl<T, S> std::fmt::Debug for WithState<'_, T, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WithState")
+            .field("len", &self.parallel.len())
+            .finish()
+    }
+}
+
+/// A [`Parallel`] with closures that run on the calling thread, returned by
+/// [`Parallel::add_local`].
+#[must_use]
+pub struct WithLocal<'a, T> {
+    parallel: Parallel<'a, T>,
+    /// The local closures, each with the number of other closures before it
+    locals: Vec<(usize, Box<dyn FnOnce() -> T + 'a>)>,
+}
+
+impl<'a, T: Send + 'a> WithLocal<'a, T> {
+    /// Adds a closure, like [`Parallel::add`].
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<F>(mut self, f: F) -> WithLocal<'a, T>
+    where
+        F: FnOnce() -> T + Send + 'a
+    {
+        self.parallel = self.parallel.add(f);
+        self
+    }
+
+    /// Adds one closure per item of `iter`, like [`Parallel::each`].
+    pub fn each<A, I, F>(mut self, iter: I, f: F) -> WithLocal<'a, T>
+    where
+        I: IntoIterator<Item = A>,
+        F: FnOnce(A) -> T + Clone + Send + 'a,
+        A: Send + 'a
+    {
+        self.parallel = self.parallel.each(iter, f);
+        self
+    }
+
+    /// Adds another closure that runs on the calling thread.
+    pub fn add_local<F>(mut self, f: F) -> WithLocal<'a, T>
+    where
+        F: FnOnce() -> T + 'a
+    {
+        self.locals.push((self.parallel.closures.len(), Box::new(f)));
+        self
+    }
+
+    /// Runs all closures and returns their results, in the order the closures
+    /// were added.
+    ///
+    /// Local closures skip the middleware, progress and cancellation of the
+    /// batch, but their panics are handled under its panic policy.
+    pub fn run(self) -> Vec<T> {
+        let WithLocal { parallel, locals } = self;
+        let policy = parallel.config.panic_policy;
+        let runner = Runner::new(&parallel.config, parallel.closures.len());
+        let (outputs, locals) = parallel.execute(&runner, || {
+            locals
+                .into_iter()
+                .map(|(pos, f)| {
+                    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| policy.call(f)));
+                    (pos, Output::One(res))
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut locals = locals.unwrap_or_else(|err| std::panic::resume_unwind(err)).into_iter().peekable();
+
+        // Put each local output back in front of the closure added after it
+        let mut merged = Vec::new();
+        for (i, output) in outputs.into_iter().enumerate() {
+            while let Some((_, local)) = locals.next_if(|&(pos, _)| pos == i) {
+                merged.push(local);
+            }
+            merged.push(output);
+        }
+        merged.extend(locals.map(|(_, local)| local));
+        policy.resolve(Output::flatten(merged))
+    }
+}
+
+impl<T> std::fmt::Debug for WithLocal<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WithLocal")
+            .field("len", &self.parallel.len())
+            .field("locals", &self.locals.len())
+            .finish()
+    }
+}
+
+/// A handle for adding closures to a running batch, given to the main
+/// closure of [`Parallel::finish_scoped`].
+pub struct BatchScope<'s, 'a, T> {
+    spawn: &'s (dyn Fn(Task<'a, T>) + 's),
+}
+
+impl<'a, T> BatchScope<'_, 'a, T> {
+    /// Runs `f` on a thread of its own as part of the batch.
+    ///
+    /// Its result is returned after those of the closures added earlier.
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: FnOnce() -> T + Send + 'a
+    {
+        (self.spawn)(Task::new(Work::Call(Box::new(f))))
+    }
+}
+
+impl<T> std::fmt::Debug for BatchScope<'_, '_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchScope").finish()
+    }
+}
+
+/// The results of a batch that did not get to finish, returned by
+/// [`Parallel::run_timeout`] and [`Parallel::run_until`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partial<T> {
+    /// Results of the closures that finished, in the order they were added.
+    pub results: Vec<T>,
+    /// Indexes of the closures that did not finish, in the order they were added.
+    pub unfinished: Vec<usize>,
+}
+
+impl<T> std::fmt::Debug for Parallel<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Parallel")
+            .field("len", &self.closures.len())
+            .finish()
+    }
+}
+
+impl<'a, T, F> Extend<F> for Parallel<'a, T>
+where
+    F: FnOnce() -> T + Send + 'a,
+    T: Send + 'a
+{
+    fn extend<I: IntoIterator<Item = F>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.closures.reserve(iter.size_hint().0);
+        for f in iter {
+            self.closures.push(Task::new(Work::Call(Box::new(f))));
+        }
+    }
+}
+
+/// Collects closures into a batch.
+///
+/// ```
+/// use easy_parallel::Parallel;
+///
+/// let v = (1..=3)
+///     .map(|i| move || i * i)
+///     .collect::<Parallel<_>>()
+///     .run();
+/// assert_eq!(v, [1, 4, 9]);
+/// ```
+impl<'a, T, F> FromIterator<F> for Parallel<'a, T>
+where
+    F: FnOnce() -> T + Send + 'a,
+    T: Send + 'a
+{
+    fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Parallel<'a, T> {
+        let mut parallel = Parallel::new();
+        parallel.extend(iter);
+        parallel
+    }
+}
+
+impl<'a, T: Send + 'a> IntoIterator for Parallel<'a, T> {
+    type Item = T;
+    type IntoIter = IntoIter<'a, T>;
+
+    fn into_iter(self) -> IntoIter<'a, T> {
+        IntoIter {
+            state: IntoIterState::Pending(Box::new(self))
+        }
+    }
+}
+
+/// An iterator over the results of a [`Parallel`], in the order the closures were added.
+///
+/// Nothing runs until the first call to `next`. Because the closures may borrow
+/// from the caller, all of them are joined before the first result is yielded.
+///
+/// ```
+/// use easy_parallel::Parallel;
+///
+/// let v: Vec<_> = Parallel::new()
+///     .each(1..=3, |i| i * 2)
+///     .into_iter()
+///     .map(|i| i + 1)
+///     .collect();
+/// assert_eq!(v, [3, 5, 7]);
+/// ```
+#[must_use]
+pub struct IntoIter<'a, T> {
+    state: IntoIterState<'a, T>
+}
+
+enum IntoIterState<'a, T> {
+    Pending(Box<Parallel<'a, T>>),
+    Running(std::vec::IntoIter<T>),
+}
+
+impl<'a, T: Send + 'a> Iterator for IntoIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if let IntoIterState::Pending(p) = &mut self.state {
+            let p = std::mem::replace(&mut **p, Parallel::new());
+            self.state = IntoIterState::Running(p.run().into_iter());
+        }
+        match &mut self.state {
+            IntoIterState::Running(iter) => iter.next(),
+            IntoIterState::Pending(_) => unreachable!(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.state {
+            IntoIterState::Pending(p) => {
+                let calls = p.closures.iter().filter(|t| matches!(t.work, Work::Call(_))).count();
+                if calls == p.closures.len() {
+                    (calls, Some(calls))
+                } else {
+                    // A producer may emit any number of results
+                    (calls, None)
+                }
+            }
+            IntoIterState::Running(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for IntoIter<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IntoIter")
+            .field("len", &match &self.state {
+                IntoIterState::Pending(p) => p.closures.len(),
+                IntoIterState::Running(iter) => iter.len(),
+            })
+            .finish()
+    }
+}
+
+/// What a closure ran into, returned by [`Parallel::run_outcomes`].
+#[derive(Debug)]
+pub struct Outcome<T> {
+    /// The result of the closure, or its panic.
+    pub result: std::thread::Result<T>,
+    /// The index of the closure, in `add` order.
+    pub index: usize,
+    /// The wall-clock time the closure took.
+    pub duration: Duration,
+    /// The thread that ran the closure.
+    pub thread: std::thread::Thread,
+}
+
+/// OS settings of a spawned thread, where `None` is the OS default
+#[derive(Clone, Copy, Default, PartialEq)]
+struct ThreadSettings {
+    priority: Option<ThreadPriority>,
+    core: Option<usize>,
+}
+
+impl ThreadSettings {
+    /// Applies the settings of `to` that differ from the current ones.
+    fn switch(&mut self, to: ThreadSettings) {
+        if to.priority != self.priority {
+            sys::set_thread_priority(to.priority.unwrap_or(ThreadPriority::Normal));
+        }
+        if to.core != self.core {
+            match to.core {
+                Some(core) => sys::set_thread_affinity(core),
+                None => sys::clear_thread_affinity(),
+            }
+        }
+        *self = to;
+    }
+}
+
+/// The OS scheduling priority of a spawned thread.
+///
+/// On Unix this maps to a nice value, on Windows to a thread priority level.
+/// It has no effect on other platforms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ThreadPriority {
+    Lowest,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    Highest,
+}
+
+/// The quality of service class of a spawned thread on Apple platforms.
+///
+/// Lower classes get less CPU time and prefer the efficiency cores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QosClass {
+    UserInteractive,
+    UserInitiated,
+    Utility,
+    Background,
+}
+
+/// What to do when closures panic.
+///
+/// Except for [`PanicPolicy::Abort`], every closure is always run to completion
+/// before a panic is propagated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PanicPolicy {
+    /// Abort the process as soon as a closure panics.
+    Abort,
+    /// Resume the panic of the first closure that panicked, in `add` order.
+    ResumeFirst,
+    /// Resume the panic of the last closure that panicked, in `add` order.
+    #[default]
+    ResumeLast,
+    /// Resume with a [`Panics`] payload holding the panics of all closures.
+    CollectAll,
+}
+
+impl PanicPolicy {
+    /// Calls `f`, aborting the process if it panics under [`PanicPolicy::Abort`].
+    fn call<T>(self, f: impl FnOnce() -> T) -> T {
+        if self != PanicPolicy::Abort {
+            return f();
+        }
+        let guard = NoPanic;
+        let t = f();
+        drop(guard);
+        t
+    }
+
+    /// Unwraps the results, resuming a panic if there was one, or aborting
+    /// under [`PanicPolicy::Abort`].
+    fn resolve<T>(self, results: Vec<std::thread::Result<T>>) -> Vec<T> {
+        let mut values = Vec::with_capacity(results.len());
+        let mut panics = Vec::new();
+        for res in results {
+            match res {
+                Ok(t) => values.push(t),
+                Err(err) => panics.push(err),
+            }
+        }
+
+        if panics.is_empty() {
+            return values;
+        }
+        match self {
+            // Panics that got past `call`, such as those of thread hooks
+            PanicPolicy::Abort => std::process::abort(),
+            PanicPolicy::ResumeLast => std::panic::resume_unwind(panics.pop().unwrap()),
+            PanicPolicy::ResumeFirst => std::panic::resume_unwind(panics.swap_remove(0)),
+            PanicPolicy::CollectAll => std::panic::resume_unwind(Box::new(Panics { payloads: panics })),
+        }
+    }
+}
+
+/// The panic payload resumed under [`PanicPolicy::CollectAll`].
+///
+/// ```
+/// use easy_parallel::{PanicPolicy, Panics, Parallel};
+/// use std::panic;
+///
+/// let err = panic::catch_unwind(|| {
+///     Parallel::new()
+///         .panic_policy(PanicPolicy::CollectAll)
+///         .each(0..4, |i| if i % 2 == 0 { panic!("{}", i) })
+///         .run()
+/// })
+/// .unwrap_err();
+/// assert_eq!(err.downcast_ref::<Panics>().unwrap().payloads().len(), 2);
+/// ```
+pub struct Panics {
+    payloads: Vec<Box<dyn std::any::Any + Send>>,
+}
+
+impl Panics {
+    /// The payloads of the panicking closures, in `add` order.
+    pub fn payloads(&self) -> &[Box<dyn std::any::Any + Send>] {
+        &self.payloads
+    }
+
+    /// Takes ownership of the payloads, in `add` order.
+    pub fn into_payloads(self) -> Vec<Box<dyn std::any::Any + Send>> {
+        self.payloads
+    }
+}
+
+impl std::fmt::Debug for Panics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Panics")
+            .field("len", &self.payloads.len())
+            .finish()
+    }
+}
+
+/// The panic of a closure, with the index of the closure in `add` order.
+///
+/// Panic payloads are wrapped in this under [`Parallel::attribute_panics`].
+pub struct ClosurePanic {
+    index: usize,
+    payload: Box<dyn std::any::Any + Send>,
+}
+
+impl ClosurePanic {
+    /// The index of the closure that panicked, in `add` order.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The original payload of the panic.
+    pub fn payload(&self) -> &(dyn std::any::Any + Send) {
+        &*self.payload
+    }
+
+    /// Takes ownership of the original payload.
+    pub fn into_payload(self) -> Box<dyn std::any::Any + Send> {
+        self.payload
+    }
+
+    /// The panic message, if the payload is a string as with `panic!`.
+    pub fn message(&self) -> Option<&str> {
+        match self.payload.downcast_ref::<&str>() {
+            Some(s) => Some(s),
+            None => self.payload.downcast_ref::<String>().map(String::as_str),
+        }
+    }
+}
+
+impl std::fmt::Debug for ClosurePanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClosurePanic")
+            .field("index", &self.index)
+            .field("message", &self.message())
+            .finish()
+    }
+}
+
+/// Whether the target can't spawn threads, as on `wasm32` without atomics
+pub(crate) const NO_THREADS: bool = cfg!(all(target_arch = "wasm32", not(target_feature = "atomics")));
+
+/// Returns the thread count set by `EASY_PARALLEL_THREADS`, read once
+pub(crate) fn env_threads() -> Option<usize> {
+    static THREADS: std::sync::OnceLock<Option<usize>> = std::sync::OnceLock::new();
+    *THREADS.get_or_init(|| {
+        let n = std::env::var("EASY_PARALLEL_THREADS").ok()?;
+        n.trim().parse().ok().filter(|&n| n > 0)
+    })
+}
+
+/// Returns `EASY_PARALLEL_THREADS`, or else the number of available CPUs
+pub(crate) fn default_threads() -> usize {
+    env_threads().unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+/// Shuffles `v` with a splitmix64 generator seeded by `seed`
+fn shuffle<T>(mut seed: u64, v: &mut [T]) {
+    let mut next = || {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    for i in (1..v.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        v.swap(i, j);
+    }
+}
+
+/// Holds threads back until a number of them are up, for `start_together`
+#[derive(Default)]
+struct StartGate {
+    /// The number of threads up, and whether they may go
+    state: sync::Mutex<(usize, bool)>,
+    cond: sync::Condvar,
+}
+
+impl StartGate {
+    /// Makes `next` wait at `gate`, if there is one, before its first call.
+    fn hold<'g, N, R>(gate: Option<&'g StartGate>, mut next: N) -> impl FnMut() -> R + Send + 'g
+    where
+        N: FnMut() -> R + Send + 'g
+    {
+        let mut arrival = Arrival(gate);
+        move || {
+            if let Some(gate) = arrival.0.take() {
+                gate.arrive();
+            }
+            next()
+        }
+    }
+
+    /// Counts this thread as up and waits until the gate opens.
+    fn arrive(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        self.cond.notify_all();
+        while !state.1 {
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    /// Counts this thread as up without waiting.
+    fn count(&self) {
+        self.state.lock().unwrap().0 += 1;
+        self.cond.notify_all();
+    }
+
+    /// Waits until `n` threads are up, then lets them all go.
+    fn open(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        while state.0 < n {
+            state = self.cond.wait(state).unwrap();
+        }
+        state.1 = true;
+        self.cond.notify_all();
+    }
+
+    /// Lets every thread go, however many are up.
+    fn release(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.cond.notify_all();
+    }
+}
+
+/// A `StartGate` still to be opened
+///
+/// If spawning the threads unwinds, as in a `thread_name` callback, the gate
+/// is released so the threads already up don't wait for the rest forever.
+struct Opening<'g>(Option<&'g StartGate>);
+
+impl Drop for Opening<'_> {
+    fn drop(&mut self) {
+        if let Some(gate) = self.0 {
+            gate.release();
+        }
+    }
+}
+
+/// A thread yet to arrive at a `StartGate`
+///
+/// A thread that panics before it arrives, as in a start hook, still counts as
+/// up so the gate opens for the others.
+struct Arrival<'g>(Option<&'g StartGate>);
+
+impl Drop for Arrival<'_> {
+    fn drop(&mut self) {
+        if let Some(gate) = self.0.take() {
+            if std::thread::panicking() {
+                gate.count();
+            }
+        }
+    }
+}
+
+/// Aborts the process if dropped while panicking
+struct NoPanic;
+
+impl Drop for NoPanic {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            std::process::abort();
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use super::{Opening, StartGate};
+    use crate::sync::Arc;
+    use loom::thread;
+
+    /// Spawns a thread that waits at `gate`, panicking first if `panics`
+    fn waiter(gate: &Arc<StartGate>, panics: bool) -> thread::JoinHandle<()> {
+        let gate = gate.clone();
+        thread::spawn(move || {
+            let mut next = StartGate::hold(Some(&*gate), || ());
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                if panics {
+                    panic!("start hook failed");
+                }
+                next()
+            }));
+        })
+    }
+
+    #[test]
+    fn gate_opens_once_every_thread_is_up() {
+        loom::model(|| {
+            let gate = Arc::new(StartGate::default());
+            let threads = [waiter(&gate, false), waiter(&gate, false)];
+            gate.open(2);
+            for thread in IntoIterator::into_iter(threads) {
+                thread.join().unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn threads_panicking_before_the_gate_still_count() {
+        loom::model(|| {
+            let gate = Arc::new(StartGate::default());
+            let threads = [waiter(&gate, true), waiter(&gate, false)];
+            gate.open(2);
+            for thread in IntoIterator::into_iter(threads) {
+                thread.join().unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn an_unopened_gate_is_released_when_spawning_unwinds() {
+        loom::model(|| {
+            let gate = Arc::new(StartGate::default());
+            let thread = waiter(&gate, false);
+            // As if spawning the second thread had panicked
+            drop(Opening(Some(&*gate)));
+            thread.join().unwrap();
+        });
     }
 }
\ No newline at end of file
@@ -0,0 +1,114 @@
+//! Chains of parallel stages connected by bounded channels.
+
+use std::fmt::Formatter;
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::Parallel;
+
+/// The number of items a channel between two stages holds by default
+const CAPACITY: usize = 16;
+
+/// A chain of stages, each with its own worker threads, where every stage
+/// feeds its outputs to the next over a bounded channel.
+///
+/// The source items are sent from a thread of their own, and each worker of a
+/// stage takes the next item from the stage before, so slow stages can be
+/// given more workers. A full channel holds the stage before it back. Outputs
+/// of the last stage come in completion order.
+///
+/// ```
+/// use easy_parallel::Pipeline;
+///
+/// let mut v = Pipeline::new(0..10)
+///     .stage(4, |i: i32| i * 2)
+///     .stage(2, |i| i + 1)
+///     .run();
+/// v.sort();
+/// assert_eq!(v, (0..10).map(|i| i * 2 + 1).collect::<Vec<_>>());
+/// ```
+#[must_use]
+pub struct Pipeline<'a, T> {
+    parallel: Parallel<'a, ()>,
+    output: mpsc::Receiver<T>,
+    capacity: usize,
+}
+
+impl<'a, T: Send + 'a> Pipeline<'a, T> {
+    /// Starts a pipeline whose first stage gets the items of `iter`.
+    pub fn new<I>(iter: I) -> Pipeline<'a, T>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'a
+    {
+        let (sender, output) = mpsc::sync_channel(CAPACITY);
+        let iter = iter.into_iter();
+        let parallel = Parallel::new().add(move || {
+            for t in iter {
+                // Stop once no stage takes items anymore
+                if sender.send(t).is_err() {
+                    break;
+                }
+            }
+        });
+        Pipeline {
+            parallel,
+            output,
+            capacity: CAPACITY,
+        }
+    }
+
+    /// Sets how many items the channels of the stages added after this hold.
+    pub fn capacity(mut self, capacity: usize) -> Pipeline<'a, T> {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Adds a stage of `workers` threads, at least one, that each map items
+    /// through `f`.
+    pub fn stage<U, F>(self, workers: usize, f: F) -> Pipeline<'a, U>
+    where
+        F: Fn(T) -> U + Send + Sync + 'a,
+        U: Send + 'a
+    {
+        let (sender, output) = mpsc::sync_channel(self.capacity);
+        let input = Arc::new(Mutex::new(self.output));
+        let f = Arc::new(f);
+        let mut parallel = self.parallel;
+        for _ in 0..workers.max(1) {
+            let (input, sender, f) = (input.clone(), sender.clone(), f.clone());
+            parallel = parallel.add(move || loop {
+                // Release the lock before running `f`
+                let t = input.lock().unwrap().recv();
+                match t {
+                    Ok(t) => {
+                        if sender.send(f(t)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+        Pipeline {
+            parallel,
+            output,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Runs every stage and returns the outputs of the last one.
+    ///
+    /// A panic in a stage is resumed once the pipeline has drained.
+    pub fn run(self) -> Vec<T> {
+        let output = self.output;
+        self.parallel.finish(|| output.iter().collect()).1
+    }
+}
+
+impl<T> std::fmt::Debug for Pipeline<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("threads", &self.parallel.len())
+            .finish()
+    }
+}
@@ -0,0 +1,170 @@
+//! A pool of long-lived threads that runs batches without spawning.
+
+use std::fmt::Formatter;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::{Job, Parallel, Spawn};
+
+/// A set of worker threads that stay alive between runs.
+///
+/// Spawning and joining OS threads on every [`Parallel::run`] can dominate the
+/// runtime of short closures. A pool pays that cost once, and runs each batch
+/// on its existing threads. Because the threads outlive any single batch, the
+/// closures must be `'static`.
+///
+/// Cloning the pool gives another handle to the same threads. Dropping the last
+/// handle waits for the threads to finish their current work.
+///
+/// On targets without threads, such as `wasm32` without atomics, the pool has
+/// none and runs each job on the calling thread as it is spawned.
+///
+/// ```
+/// use easy_parallel::{Parallel, ParallelPool};
+///
+/// let pool = ParallelPool::new(4);
+/// for n in 0..3 {
+///     let v = pool.run(Parallel::new().each(0..8, move |i| i * n));
+///     assert_eq!(v, (0..8).map(|i| i * n).collect::<Vec<_>>());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ParallelPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    sender: Option<mpsc::Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ParallelPool {
+    /// Starts a pool with `threads` worker threads, at least one where the
+    /// target has threads.
+    pub fn new(threads: usize) -> ParallelPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = if crate::NO_THREADS { 0 } else { threads.max(1) };
+        let threads = (0..threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                std::thread::spawn(move || loop {
+                    // Release the lock before running the job
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        // Keep the worker alive if a job panics
+                        Ok(job) => drop(std::panic::catch_unwind(std::panic::AssertUnwindSafe(job))),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        ParallelPool {
+            inner: Arc::new(Inner {
+                sender: Some(sender),
+                threads,
+            }),
+        }
+    }
+
+    /// Returns the process-wide pool, starting it on first use with one thread
+    /// per available CPU, or as many as the `EASY_PARALLEL_THREADS` environment
+    /// variable says.
+    #[cfg(feature = "global-pool")]
+    pub fn global() -> &'static ParallelPool {
+        static GLOBAL: std::sync::OnceLock<ParallelPool> = std::sync::OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            ParallelPool::new(crate::default_threads())
+        })
+    }
+
+    /// Returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.inner.threads.len()
+    }
+
+    /// Starts an empty batch that runs on the pool.
+    ///
+    /// Any terminator of the batch, such as [`Parallel::run`] or
+    /// [`Parallel::finish`], then runs its closures on the pool's threads.
+    ///
+    /// ```
+    /// use easy_parallel::ParallelPool;
+    ///
+    /// let pool = ParallelPool::new(2);
+    /// let (v, main) = pool.batch().each(0..3, |i| i * 2).finish(|| "main");
+    /// assert_eq!(v, [0, 2, 4]);
+    /// assert_eq!(main, "main");
+    /// ```
+    ///
+    /// A [`max_threads`](Parallel::max_threads) limit keeps the rest of the
+    /// closures queued while the first ones run:
+    ///
+    /// ```
+    /// use easy_parallel::ParallelPool;
+    ///
+    /// let pool = ParallelPool::new(4);
+    /// let sum = pool
+    ///     .batch()
+    ///     .max_threads(2)
+    ///     .each(1..=6, |i| i)
+    ///     .run_streaming(|results| results.iter().sum::<i32>());
+    /// assert_eq!(sum, 21);
+    /// ```
+    pub fn batch<T: Send + 'static>(&self) -> Parallel<'static, T> {
+        Parallel::new().with_spawner(self.clone())
+    }
+
+    /// Runs all closures of `parallel` on the pool and returns their results,
+    /// in the order the closures were added.
+    ///
+    /// This is [`Parallel::run`] with the pool as its backend, see
+    /// [`Parallel::with_spawner`]. Running a batch from inside one of the pool's
+    /// closures can deadlock once every worker thread is waiting.
+    pub fn run<T: Send + 'static>(&self, parallel: Parallel<'static, T>) -> Vec<T> {
+        parallel.with_spawner(self.clone()).run()
+    }
+
+    /// Runs all closures of `parallel` on the pool and returns their results,
+    /// with panics caught as values.
+    ///
+    /// See [`ParallelPool::run`] and [`Parallel::run_catching`].
+    pub fn run_catching<T: Send + 'static>(
+        &self,
+        parallel: Parallel<'static, T>,
+    ) -> Vec<std::thread::Result<T>> {
+        parallel.with_spawner(self.clone()).run_catching()
+    }
+}
+
+impl Spawn for ParallelPool {
+    fn spawn(&self, job: Job) {
+        if self.inner.threads.is_empty() {
+            // As a worker would, outlive a panicking job
+            drop(std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)));
+            return;
+        }
+        self.inner.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Disconnect the channel so the workers stop once it is drained
+        drop(self.sender.take());
+        for thread in self.threads.drain(..) {
+            // The last handle may be dropped by a job on one of the workers
+            if thread.thread().id() != std::thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ParallelPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParallelPool")
+            .field("threads", &self.threads())
+            .finish()
+    }
+}
@@ -0,0 +1,256 @@
+//! Batches whose items push more items while they run.
+
+use std::collections::VecDeque;
+use std::fmt::Formatter;
+
+use crate::spawn::JobScope;
+use crate::sync::{Arc, Condvar, Mutex};
+use crate::task::{self, Output, Queue, Runner, Task, Tasks, Work};
+use crate::{jobs, Config, ThreadSettings};
+
+/// A handle for pushing more items to
+/// [`Parallel::run_recursive`](crate::Parallel::run_recursive).
+///
+/// It can be cloned and sent to other threads; items pushed after the run has
+/// finished are dropped with it.
+pub struct WorkQueue<A> {
+    live: Arc<Live<A>>,
+}
+
+/// The items of a run waiting for a thread
+struct Live<A> {
+    pending: Mutex<Pending<A>>,
+    cond: Condvar,
+}
+
+struct Pending<A> {
+    /// Queued items, with the index of their result
+    items: VecDeque<(usize, A)>,
+    /// The index of the next item pushed
+    next: usize,
+    /// The tasks taken but not done yet
+    in_flight: usize,
+}
+
+impl<A> WorkQueue<A> {
+    /// Queues `item`, to be picked up by the next free thread.
+    pub fn push(&self, item: A) {
+        let mut pending = self.live.pending.lock().unwrap();
+        let index = pending.next;
+        pending.next += 1;
+        pending.items.push_back((index, item));
+        self.live.cond.notify_one();
+    }
+}
+
+impl<A> Clone for WorkQueue<A> {
+    fn clone(&self) -> WorkQueue<A> {
+        WorkQueue {
+            live: self.live.clone(),
+        }
+    }
+}
+
+impl<A> std::fmt::Debug for WorkQueue<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkQueue")
+            .field("len", &self.live.pending.lock().unwrap().items.len())
+            .finish()
+    }
+}
+
+/// One run of a batch and the items pushed during it
+struct Run<'a, A, T, F> {
+    closures: Mutex<Queue<'a, T>>,
+    queue: WorkQueue<A>,
+    f: Mutex<F>,
+    /// Items from this index on were pushed during the run
+    pushed: usize,
+    runner: Runner<'a>,
+    config: Config<'a>,
+    outputs: Mutex<Vec<Option<Output<T>>>>,
+}
+
+/// Runs `closures`, then `f` on each of `roots` and on every item pushed to
+/// its queue, on at most `max_threads` threads including the calling one.
+///
+/// Returns once no item is queued or running, with the outputs of the closures
+/// in `add` order followed by those of the items in push order.
+pub(crate) fn run<'a, A, T, I, F>(closures: Tasks<'a, T>, config: Config<'a>, roots: I, f: F) -> Vec<Output<T>>
+where
+    I: IntoIterator<Item = A>,
+    F: FnOnce(A, &WorkQueue<A>) -> T + Clone + Send + 'a,
+    A: Send + 'a,
+    T: Send + 'a
+{
+    let run = Arc::new(Run::new(closures, config, roots, f));
+
+    let config = &run.config;
+    if config.is_sequential() {
+        run.work(None);
+    } else {
+        let workers = config.max_threads().unwrap_or_else(crate::default_threads);
+        let scope = config.spawner.clone().map(JobScope::new);
+        std::thread::scope(|s| {
+            // The calling thread is the last worker
+            for w in 0..workers - 1 {
+                if w > 0 {
+                    config.stagger();
+                }
+                let theirs = run.clone();
+                let job: jobs::TaskJob<'a> = Box::new(move || theirs.work(Some(w)));
+                match &scope {
+                    Some(scope) => scope.spawn(job),
+                    None => {
+                        let builder = config.thread_builder(w);
+                        jobs::spawn_or_run(|job| builder.spawn_scoped(s, job).map(drop), config.hooked(w, job));
+                    }
+                }
+            }
+            run.work(None);
+        });
+    }
+    run.outputs()
+}
+
+impl<'a, A, T, F> Run<'a, A, T, F>
+where
+    F: FnOnce(A, &WorkQueue<A>) -> T + Clone + Send + 'a,
+    A: Send + 'a,
+    T: Send + 'a
+{
+    /// Queues `roots` behind `closures`, without running anything yet.
+    fn new<I>(closures: Tasks<'a, T>, config: Config<'a>, roots: I, f: F) -> Run<'a, A, T, F>
+    where
+        I: IntoIterator<Item = A>
+    {
+        let queue = WorkQueue {
+            live: Arc::new(Live {
+                pending: Mutex::new(Pending {
+                    items: VecDeque::new(),
+                    next: closures.len(),
+                    in_flight: 0,
+                }),
+                cond: Condvar::new(),
+            }),
+        };
+        for root in roots {
+            queue.push(root);
+        }
+        let pushed = queue.live.pending.lock().unwrap().next;
+        Run {
+            closures: Mutex::new(task::start_order(closures)),
+            queue,
+            f: Mutex::new(f),
+            pushed,
+            runner: Runner::new(&config, pushed),
+            config,
+            outputs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes the outputs stored so far, in index order.
+    fn outputs(&self) -> Vec<Output<T>> {
+        let outputs = std::mem::take(&mut *self.outputs.lock().unwrap());
+        outputs.into_iter().flatten().collect()
+    }
+
+    /// Runs tasks until none are queued or running, with the settings of
+    /// thread `worker` unless this is the calling thread.
+    fn work(&self, worker: Option<usize>) {
+        let mut current = ThreadSettings::default();
+        while let Some((i, task)) = self.next() {
+            if let Some(w) = worker {
+                current.switch(ThreadSettings {
+                    priority: task.thread_priority.or(self.config.thread_priority),
+                    core: task.core.or_else(|| self.config.core(w)),
+                });
+            }
+            let output = self.runner.run(i, task.work);
+            self.done(i, output);
+        }
+        current.switch(ThreadSettings::default());
+    }
+
+    /// Takes the next closure or item, waiting for the running ones to push
+    /// more if none is queued.
+    fn next(&self) -> Option<(usize, Task<'a, T>)> {
+        let live = &self.queue.live;
+        let mut pending = live.pending.lock().unwrap();
+        if let Some(next) = self.closures.lock().unwrap().next() {
+            pending.in_flight += 1;
+            return Some(next);
+        }
+        loop {
+            if let Some((i, item)) = pending.items.pop_front() {
+                pending.in_flight += 1;
+                drop(pending);
+                if i >= self.pushed {
+                    self.runner.add_task();
+                }
+                let (f, queue) = (self.f.lock().unwrap().clone(), self.queue.clone());
+                return Some((i, Task::new(Work::Call(Box::new(move || f(item, &queue))))));
+            }
+            if pending.in_flight == 0 {
+                return None;
+            }
+            pending = live.cond.wait(pending).unwrap();
+        }
+    }
+
+    /// Stores the output of task `i`, waking the waiting threads if it was the
+    /// last one.
+    fn done(&self, i: usize, output: Output<T>) {
+        {
+            let mut outputs = self.outputs.lock().unwrap();
+            if outputs.len() <= i {
+                outputs.resize_with(i + 1, || None);
+            }
+            outputs[i] = Some(output);
+        }
+        let live = &self.queue.live;
+        let mut pending = live.pending.lock().unwrap();
+        pending.in_flight -= 1;
+        if pending.in_flight == 0 && pending.items.is_empty() {
+            live.cond.notify_all();
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use super::{Run, WorkQueue};
+    use crate::sync::Arc;
+    use crate::task::{Output, Tasks};
+    use crate::Config;
+    use loom::thread;
+
+    #[test]
+    fn workers_wait_for_items_pushed_by_running_ones() {
+        loom::model(|| {
+            let f = |n: usize, queue: &WorkQueue<usize>| {
+                if n == 0 {
+                    queue.push(1);
+                }
+                n
+            };
+            let run = Arc::new(Run::new(Tasks::new(), Config::default(), vec![0], f));
+            let worker = {
+                let run = run.clone();
+                thread::spawn(move || run.work(None))
+            };
+            run.work(None);
+            worker.join().unwrap();
+            // Both workers return, once the pushed item ran too
+            let values: Vec<_> = run
+                .outputs()
+                .into_iter()
+                .map(|output| match output {
+                    Output::One(Ok(n)) => n,
+                    _ => unreachable!(),
+                })
+                .collect();
+            assert_eq!(values, [0, 1]);
+        });
+    }
+}
@@ -0,0 +1,95 @@
+//! Settings shared by many batches.
+
+use std::fmt::Formatter;
+use std::sync::Arc;
+
+use crate::{PanicPolicy, Parallel};
+
+/// Thread and panic settings made once and applied to any number of batches
+/// with [`Parallel::with_config`].
+///
+/// Settings left unset keep the batch's own. Timeouts stay with the
+/// terminators, [`Parallel::run_timeout`] and [`Parallel::run_until`].
+///
+/// ```
+/// use easy_parallel::{PanicPolicy, Parallel, RunConfig};
+///
+/// let config = RunConfig::new()
+///     .max_threads(2)
+///     .thread_name(|i| format!("job-{}", i))
+///     .panic_policy(PanicPolicy::ResumeFirst);
+///
+/// let a = Parallel::new().with_config(&config).each(0..4, |i| i).run();
+/// let b = Parallel::new().with_config(&config).each(0..4, |i| i * i).run();
+/// assert_eq!(a, [0, 1, 2, 3]);
+/// assert_eq!(b, [0, 1, 4, 9]);
+/// ```
+#[derive(Clone, Default)]
+pub struct RunConfig {
+    max_threads: Option<usize>,
+    thread_name: Option<Arc<dyn Fn(usize) -> String + Send + Sync>>,
+    stack_size: Option<usize>,
+    panic_policy: Option<PanicPolicy>,
+}
+
+impl RunConfig {
+    /// Creates a config with nothing set.
+    pub fn new() -> RunConfig {
+        RunConfig::default()
+    }
+
+    /// Sets [`Parallel::max_threads`].
+    pub fn max_threads(mut self, n: usize) -> RunConfig {
+        self.max_threads = Some(n.max(1));
+        self
+    }
+
+    /// Sets [`Parallel::thread_name`].
+    pub fn thread_name<F>(mut self, f: F) -> RunConfig
+    where
+        F: Fn(usize) -> String + Send + Sync + 'static
+    {
+        self.thread_name = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets [`Parallel::stack_size`].
+    pub fn stack_size(mut self, size: usize) -> RunConfig {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Sets [`Parallel::panic_policy`].
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> RunConfig {
+        self.panic_policy = Some(policy);
+        self
+    }
+
+    /// Applies the settings that are set to `parallel`.
+    pub(crate) fn apply<'a, T>(&self, mut parallel: Parallel<'a, T>) -> Parallel<'a, T> {
+        if let Some(n) = self.max_threads {
+            parallel = parallel.max_threads(n);
+        }
+        if let Some(name) = &self.thread_name {
+            parallel.config.thread_name = Some(name.clone());
+        }
+        if let Some(size) = self.stack_size {
+            parallel = parallel.stack_size(size);
+        }
+        if let Some(policy) = self.panic_policy {
+            parallel = parallel.panic_policy(policy);
+        }
+        parallel
+    }
+}
+
+impl std::fmt::Debug for RunConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunConfig")
+            .field("max_threads", &self.max_threads)
+            .field("thread_name", &self.thread_name.is_some())
+            .field("stack_size", &self.stack_size)
+            .field("panic_policy", &self.panic_policy)
+            .finish()
+    }
+}
@@ -0,0 +1,234 @@
+//! Backends running the closures of a batch.
+
+use std::marker::PhantomData;
+
+use crate::sync::{Arc, Condvar, Mutex};
+
+/// A unit of work handed to a [`Spawn`] backend.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A backend that runs jobs, such as a thread pool.
+///
+/// By default every closure runs on a thread of its own; see
+/// [`Parallel::with_spawner`](crate::Parallel::with_spawner) to use a backend
+/// instead. Closures send their results back from within the job, so the job
+/// may run on any thread, at any time, but it must run: a job that is dropped
+/// without running makes the batch panic.
+///
+/// This is also the hook for platforms without `std::thread`, such as an
+/// RTOS task spawner. The crate itself still needs `std`: it catches panics,
+/// hands results back through `std::sync::Mutex` slots or, from backends, over
+/// a channel, and keeps the cancellation flag in a thread local, none of which
+/// `core` and `alloc` provide.
+///
+/// Any `Fn(Job)` is a backend, so plugging in another pool is a one-liner:
+///
+/// ```
+/// use easy_parallel::Parallel;
+///
+/// // Spawn a detached thread per job
+/// let v = Parallel::new()
+///     .with_spawner(|job| drop(std::thread::spawn(job)))
+///     .each(0..4, |i| i * 2)
+///     .run();
+/// assert_eq!(v, [0, 2, 4, 6]);
+/// ```
+pub trait Spawn: Send + Sync {
+    /// Runs `job`, now or later, on this or another thread.
+    fn spawn(&self, job: Job);
+}
+
+impl<F: Fn(Job) + Send + Sync> Spawn for F {
+    fn spawn(&self, job: Job) {
+        self(job)
+    }
+}
+
+/// A backend running every job right away on the calling thread.
+///
+/// Useful in tests, and anywhere spawning threads is undesirable.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Inline;
+
+impl Spawn for Inline {
+    fn spawn(&self, job: Job) {
+        job()
+    }
+}
+
+/// The jobs of one run handed to a [`Spawn`] backend, which may borrow from
+/// the batch.
+///
+/// The backend only gets `'static` jobs that take their work from a shared
+/// cell. Dropping the scope takes back the work of jobs that haven't started
+/// and waits for the running ones, so none of it outlives `'a`.
+pub(crate) struct JobScope<'a> {
+    spawner: std::sync::Arc<dyn Spawn>,
+    cells: Mutex<Vec<Cell>>,
+    running: Arc<Running>,
+    _jobs: PhantomData<&'a ()>,
+}
+
+/// The work of one job, until a thread takes it
+type Cell = Arc<Mutex<Option<Job>>>;
+
+/// The number of jobs whose work hasn't finished or been taken back
+#[derive(Default)]
+struct Running {
+    count: Mutex<usize>,
+    cond: Condvar,
+}
+
+impl Running {
+    fn done(&self) {
+        let mut count = self.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.cond.notify_all();
+        }
+    }
+}
+
+/// The job handed to the backend, running the work in `cell` if it is still
+/// there
+struct Handoff {
+    cell: Cell,
+    running: Arc<Running>,
+}
+
+impl Handoff {
+    fn run(self) {
+        let work = self.cell.lock().unwrap().take();
+        if let Some(work) = work {
+            let _done = Done(&self.running);
+            work();
+        }
+    }
+}
+
+impl Drop for Handoff {
+    /// Drops the work if the backend dropped the job before it ran.
+    fn drop(&mut self) {
+        let work = self.cell.lock().unwrap().take();
+        if let Some(work) = work {
+            drop(work);
+            self.running.done();
+        }
+    }
+}
+
+/// Counts a job as done when dropped, even while unwinding
+struct Done<'r>(&'r Running);
+
+impl Drop for Done<'_> {
+    fn drop(&mut self) {
+        self.0.done();
+    }
+}
+
+impl<'a> JobScope<'a> {
+    pub(crate) fn new(spawner: std::sync::Arc<dyn Spawn>) -> JobScope<'a> {
+        JobScope {
+            spawner,
+            cells: Mutex::new(Vec::new()),
+            running: Arc::default(),
+            _jobs: PhantomData,
+        }
+    }
+
+    /// Hands `job` to the backend.
+    pub(crate) fn spawn(&self, job: Box<dyn FnOnce() + Send + 'a>) {
+        // SAFETY: only the lifetime changes, and the scope takes the job back
+        // or waits for it to finish before `'a` ends
+        let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job) };
+        let cell = Arc::new(Mutex::new(Some(job)));
+        *self.running.count.lock().unwrap() += 1;
+        self.cells.lock().unwrap().push(cell.clone());
+        let handoff = Handoff {
+            cell,
+            running: self.running.clone(),
+        };
+        self.spawner.spawn(Box::new(move || handoff.run()));
+    }
+}
+
+impl Drop for JobScope<'_> {
+    fn drop(&mut self) {
+        for cell in self.cells.get_mut().unwrap().drain(..) {
+            let work = cell.lock().unwrap().take();
+            if let Some(work) = work {
+                drop(work);
+                self.running.done();
+            }
+        }
+        let mut count = self.running.count.lock().unwrap();
+        while *count > 0 {
+            count = self.running.cond.wait(count).unwrap();
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use super::{Job, JobScope, Spawn};
+    use crate::sync::{AtomicUsize, Ordering};
+    use loom::sync::Mutex;
+    use loom::thread::{self, JoinHandle};
+
+    /// Runs each job on a loom thread, catching its panic
+    #[derive(Default)]
+    struct Threads(Mutex<Vec<JoinHandle<()>>>);
+
+    impl Spawn for Threads {
+        fn spawn(&self, job: Job) {
+            let handle = thread::spawn(move || {
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+            });
+            self.0.lock().unwrap().push(handle);
+        }
+    }
+
+    #[test]
+    fn dropping_the_scope_waits_for_or_takes_back_every_job() {
+        loom::model(|| {
+            let threads = std::sync::Arc::new(Threads::default());
+            let runs = AtomicUsize::new(0);
+            {
+                let scope = JobScope::new(threads.clone());
+                scope.spawn(Box::new(|| {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                }));
+                scope.spawn(Box::new(|| panic!("bad job")));
+            }
+            // Nothing borrowed runs once the scope is gone
+            let seen = runs.load(Ordering::SeqCst);
+            for handle in threads.0.lock().unwrap().drain(..) {
+                handle.join().unwrap();
+            }
+            assert!(seen <= 1);
+            assert_eq!(runs.load(Ordering::SeqCst), seen);
+        });
+    }
+
+    #[test]
+    fn dropped_jobs_count_as_done() {
+        loom::model(|| {
+            let dropped = AtomicUsize::new(0);
+            {
+                let scope = JobScope::new(std::sync::Arc::new(drop::<Job>));
+                let guard = Counted(&dropped);
+                scope.spawn(Box::new(move || drop(guard)));
+            }
+            assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    /// Counts itself in `.0` when dropped
+    struct Counted<'c>(&'c AtomicUsize);
+
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+//! Synchronization primitives shared between threads, which are loom's under
+//! `--cfg loom`.
+//!
+//! The flags, counters, gates and hand-off cells the batches are built on go
+//! through here, and each module models its own next to the code. loom has no
+//! scoped threads, so the models drive these pieces from loom threads rather
+//! than whole batches. They run with
+//! `RUSTFLAGS="--cfg loom" cargo test --lib --release`.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(loom)]
+pub(crate) use loom::sync::{Arc, Condvar, Mutex};
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Arc, Condvar, Mutex};
@@ -0,0 +1,239 @@
+//! Platform specific thread settings and system load.
+
+use crate::{QosClass, ThreadPriority};
+
+/// Sets the priority of the calling thread, ignoring failures.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_thread_priority(priority: ThreadPriority) {
+    let nice = match priority {
+        ThreadPriority::Lowest => 19,
+        ThreadPriority::BelowNormal => 10,
+        ThreadPriority::Normal => 0,
+        ThreadPriority::AboveNormal => -5,
+        ThreadPriority::Highest => -10,
+    };
+    // On Linux the nice value is per thread
+    unsafe {
+        let tid = libc::gettid();
+        libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice);
+    }
+}
+
+/// Sets the priority of the calling thread, ignoring failures.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn set_thread_priority(priority: ThreadPriority) {
+    let (min, max) = unsafe {
+        (
+            libc::sched_get_priority_min(libc::SCHED_OTHER),
+            libc::sched_get_priority_max(libc::SCHED_OTHER),
+        )
+    };
+    let step = (max - min) / 4;
+    let level = match priority {
+        ThreadPriority::Lowest => 0,
+        ThreadPriority::BelowNormal => 1,
+        ThreadPriority::Normal => 2,
+        ThreadPriority::AboveNormal => 3,
+        ThreadPriority::Highest => 4,
+    };
+    unsafe {
+        let param = libc::sched_param { sched_priority: min + step * level };
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_OTHER, &param);
+    }
+}
+
+/// Sets the priority of the calling thread, ignoring failures.
+#[cfg(windows)]
+pub(crate) fn set_thread_priority(priority: ThreadPriority) {
+    let level = match priority {
+        ThreadPriority::Lowest => -2,
+        ThreadPriority::BelowNormal => -1,
+        ThreadPriority::Normal => 0,
+        ThreadPriority::AboveNormal => 1,
+        ThreadPriority::Highest => 2,
+    };
+    unsafe {
+        windows::SetThreadPriority(windows::GetCurrentThread(), level);
+    }
+}
+
+/// Sets the priority of the calling thread, ignoring failures.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn set_thread_priority(_priority: ThreadPriority) {}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+thread_local! {
+    /// The mask of the calling thread before it was first pinned
+    static UNPINNED: std::cell::Cell<Option<libc::cpu_set_t>> = const { std::cell::Cell::new(None) };
+}
+
+/// Pins the calling thread to a CPU core, ignoring failures.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_thread_affinity(core: usize) {
+    if core < libc::CPU_SETSIZE as usize {
+        unsafe {
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            if UNPINNED.with(|unpinned| unpinned.get().is_none()) {
+                let mut original: libc::cpu_set_t = std::mem::zeroed();
+                if libc::sched_getaffinity(0, size, &mut original) == 0 {
+                    UNPINNED.with(|unpinned| unpinned.set(Some(original)));
+                }
+            }
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(core, &mut set);
+            libc::sched_setaffinity(0, size, &set);
+        }
+    }
+}
+
+/// Puts the calling thread back on the cores it ran on before it was pinned,
+/// ignoring failures.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn clear_thread_affinity() {
+    if let Some(original) = UNPINNED.with(|unpinned| unpinned.take()) {
+        unsafe {
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &original);
+        }
+    }
+}
+
+/// Pins the calling thread to a CPU core, ignoring failures.
+#[cfg(windows)]
+pub(crate) fn set_thread_affinity(core: usize) {
+    if core < usize::BITS as usize {
+        unsafe {
+            windows::SetThreadAffinityMask(windows::GetCurrentThread(), 1 << core);
+        }
+    }
+}
+
+/// Lets the calling thread run on any CPU core again, ignoring failures.
+#[cfg(windows)]
+pub(crate) fn clear_thread_affinity() {
+    unsafe {
+        let (mut process, mut system) = (0, 0);
+        if windows::GetProcessAffinityMask(windows::GetCurrentProcess(), &mut process, &mut system) != 0 {
+            windows::SetThreadAffinityMask(windows::GetCurrentThread(), process);
+        }
+    }
+}
+
+/// Pins the calling thread to a CPU core, ignoring failures.
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+pub(crate) fn set_thread_affinity(_core: usize) {}
+
+/// Lets the calling thread run on any CPU core again, ignoring failures.
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+pub(crate) fn clear_thread_affinity() {}
+
+/// Sets the QoS class of the calling thread, ignoring failures.
+#[cfg(target_vendor = "apple")]
+pub(crate) fn set_qos_class(class: QosClass) {
+    // Values of `qos_class_t` from <sys/qos.h>
+    let class = match class {
+        QosClass::UserInteractive => 0x21,
+        QosClass::UserInitiated => 0x19,
+        QosClass::Utility => 0x11,
+        QosClass::Background => 0x09,
+    };
+    unsafe {
+        apple::pthread_set_qos_class_self_np(class, 0);
+    }
+}
+
+/// Sets the QoS class of the calling thread, ignoring failures.
+#[cfg(not(target_vendor = "apple"))]
+pub(crate) fn set_qos_class(_class: QosClass) {}
+
+/// Returns the CPU cores of each NUMA node, in node order.
+#[cfg(target_os = "linux")]
+pub(crate) fn numa_nodes() -> Vec<Vec<usize>> {
+    let mut nodes = Vec::new();
+    for node in 0.. {
+        let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+        match std::fs::read_to_string(path) {
+            Ok(list) => nodes.push(parse_cpu_list(&list)),
+            Err(_) => break,
+        }
+    }
+    nodes
+}
+
+/// Returns the CPU cores of each NUMA node, in node order.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn numa_nodes() -> Vec<Vec<usize>> {
+    Vec::new()
+}
+
+/// Parses a list of cores like `0-3,8,10-11`, skipping what doesn't parse.
+#[cfg(target_os = "linux")]
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cores = Vec::new();
+    for part in list.trim().split(',') {
+        let mut bounds = part.splitn(2, '-').map(|n| n.parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), None) => cores.push(first),
+            (Some(Ok(first)), Some(Ok(last))) => cores.extend(first..=last),
+            _ => {}
+        }
+    }
+    cores
+}
+
+/// Returns the number of runnable threads on the system averaged over the
+/// last minute, if the platform tells.
+#[cfg(any(
+    target_os = "linux",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+pub(crate) fn load_average() -> Option<f64> {
+    let mut load = [0.0];
+    let n = unsafe { libc::getloadavg(load.as_mut_ptr(), 1) };
+    if n == 1 {
+        Some(load[0])
+    } else {
+        None
+    }
+}
+
+/// Returns the number of runnable threads on the system averaged over the
+/// last minute, if the platform tells.
+#[cfg(not(any(
+    target_os = "linux",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+pub(crate) fn load_average() -> Option<f64> {
+    None
+}
+
+#[cfg(target_vendor = "apple")]
+mod apple {
+    extern "C" {
+        pub(crate) fn pthread_set_qos_class_self_np(class: u32, relative_priority: i32) -> i32;
+    }
+}
+
+#[cfg(windows)]
+#[allow(non_snake_case)]
+mod windows {
+    pub(crate) type Handle = *mut core::ffi::c_void;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub(crate) fn GetCurrentThread() -> Handle;
+        pub(crate) fn GetCurrentProcess() -> Handle;
+        pub(crate) fn GetProcessAffinityMask(
+            process: Handle,
+            process_mask: *mut usize,
+            system_mask: *mut usize,
+        ) -> i32;
+        pub(crate) fn SetThreadPriority(thread: Handle, priority: i32) -> i32;
+        pub(crate) fn SetThreadAffinityMask(thread: Handle, mask: usize) -> usize;
+    }
+}
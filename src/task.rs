@@ -0,0 +1,516 @@
+//! The closures queued in a batch.
+
+use std::any::Any;
+use std::iter::FromIterator;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use smallvec::SmallVec;
+
+use crate::cancel::Cancel;
+use crate::sync::{self, AtomicUsize, Ordering};
+use crate::{ClosurePanic, Config, PanicPolicy, ThreadPriority};
+
+/// A queued closure with its own settings
+pub(crate) struct Task<'a, T> {
+    pub(crate) work: Work<'a, T>,
+    pub(crate) thread_priority: Option<ThreadPriority>,
+    pub(crate) core: Option<usize>,
+    /// Tasks with higher priorities start first
+    pub(crate) priority: i32,
+}
+
+/// The closures of a batch, stored inline for small batches
+///
+/// Unlike a `SmallVec`, it is covariant in `'a`, so a batch of `'static`
+/// closures can be used as a shorter-lived one.
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Tasks<'a, T> {
+    Inline(usize, [Option<Task<'a, T>>; INLINE]),
+    Heap(Vec<Task<'a, T>>),
+}
+
+/// The number of closures kept inline
+const INLINE: usize = 4;
+
+impl<'a, T> Tasks<'a, T> {
+    pub(crate) fn new() -> Tasks<'a, T> {
+        Tasks::Inline(0, [None, None, None, None])
+    }
+
+    pub(crate) fn with_capacity(n: usize) -> Tasks<'a, T> {
+        if n <= INLINE {
+            Tasks::new()
+        } else {
+            Tasks::Heap(Vec::with_capacity(n))
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Tasks::Inline(len, _) => *len,
+            Tasks::Heap(tasks) => tasks.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn push(&mut self, task: Task<'a, T>) {
+        self.reserve(1);
+        match self {
+            Tasks::Inline(len, tasks) => {
+                tasks[*len] = Some(task);
+                *len += 1;
+            }
+            Tasks::Heap(tasks) => tasks.push(task),
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Task<'a, T>> {
+        match self {
+            Tasks::Inline(0, _) => None,
+            Tasks::Inline(len, tasks) => {
+                *len -= 1;
+                tasks[*len].take()
+            }
+            Tasks::Heap(tasks) => tasks.pop(),
+        }
+    }
+
+    /// Makes room for `additional` more tasks, moving them to the heap if
+    /// they no longer fit inline.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        match self {
+            Tasks::Inline(len, tasks) if *len + additional > INLINE => {
+                let mut heap = Vec::with_capacity((*len + additional).max(2 * INLINE));
+                heap.extend(tasks.iter_mut().filter_map(Option::take));
+                *self = Tasks::Heap(heap);
+            }
+            Tasks::Inline(..) => {}
+            Tasks::Heap(tasks) => tasks.reserve(additional),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Task<'a, T>> {
+        let (inline, heap) = match self {
+            Tasks::Inline(_, tasks) => (&tasks[..], &[][..]),
+            Tasks::Heap(tasks) => (&[][..], &tasks[..]),
+        };
+        inline.iter().flatten().chain(heap)
+    }
+}
+
+impl<T> Default for Tasks<'_, T> {
+    fn default() -> Self {
+        Tasks::new()
+    }
+}
+
+impl<'a, T> Extend<Task<'a, T>> for Tasks<'a, T> {
+    fn extend<I: IntoIterator<Item = Task<'a, T>>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for task in iter {
+            self.push(task);
+        }
+    }
+}
+
+impl<'a, T> FromIterator<Task<'a, T>> for Tasks<'a, T> {
+    fn from_iter<I: IntoIterator<Item = Task<'a, T>>>(iter: I) -> Self {
+        let mut tasks = Tasks::new();
+        tasks.extend(iter);
+        tasks
+    }
+}
+
+impl<'a, T> IntoIterator for Tasks<'a, T> {
+    type Item = Task<'a, T>;
+    type IntoIter = IntoIter<'a, T>;
+
+    fn into_iter(self) -> IntoIter<'a, T> {
+        match self {
+            Tasks::Inline(_, tasks) => IntoIter::Inline(IntoIterator::into_iter(tasks)),
+            Tasks::Heap(tasks) => IntoIter::Heap(tasks.into_iter()),
+        }
+    }
+}
+
+/// The tasks of a batch, in `add` order
+// Keeping the tasks inline is the point of the large variant
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum IntoIter<'a, T> {
+    Inline(std::array::IntoIter<Option<Task<'a, T>>, INLINE>),
+    Heap(std::vec::IntoIter<Task<'a, T>>),
+}
+
+impl<'a, T> Iterator for IntoIter<'a, T> {
+    type Item = Task<'a, T>;
+
+    fn next(&mut self) -> Option<Task<'a, T>> {
+        match self {
+            // The inline tasks are all at the front
+            IntoIter::Inline(tasks) => tasks.next().flatten(),
+            IntoIter::Heap(tasks) => tasks.next(),
+        }
+    }
+}
+
+/// The tasks of a batch with their indexes, in the order they start
+pub(crate) type Queue<'a, T> = <SmallVec<[(usize, Task<'a, T>); 4]> as IntoIterator>::IntoIter;
+
+/// Queues `tasks` by priority, keeping `add` order among equal priorities.
+pub(crate) fn start_order<T>(tasks: Tasks<'_, T>) -> Queue<'_, T> {
+    let mut queue: SmallVec<[_; 4]> = tasks.into_iter().enumerate().collect();
+    queue.sort_by_key(|(_, task)| std::cmp::Reverse(task.priority));
+    queue.into_iter()
+}
+
+type Producer<'a, T> = Box<dyn FnOnce(&mut dyn FnMut(T)) + Send + 'a>;
+
+/// The body of a task
+pub(crate) enum Work<'a, T> {
+    /// A closure returning one result
+    Call(Box<dyn FnOnce() -> T + Send + 'a>),
+    /// A closure emitting any number of results
+    Produce(Producer<'a, T>),
+}
+
+/// What a task produced
+pub(crate) enum Output<T> {
+    /// The result of a call
+    One(std::thread::Result<T>),
+    /// The values a producer emitted, and its panic if it had one
+    Many(Vec<T>, Option<Box<dyn Any + Send>>),
+    /// Nothing, because the batch was cancelled before the task started
+    Skipped,
+}
+
+impl<'a, T> Task<'a, T> {
+    pub(crate) fn new(work: Work<'a, T>) -> Task<'a, T> {
+        Task {
+            work,
+            thread_priority: None,
+            core: None,
+            priority: 0,
+        }
+    }
+
+    /// Keeps the settings, but passes the results of the closure through `g`.
+    pub(crate) fn map<U, G>(self, g: G) -> Task<'a, U>
+    where
+        G: FnOnce(T) -> U + Clone + Send + 'a,
+        T: 'a
+    {
+        let work = match self.work {
+            Work::Call(f) => Work::Call(Box::new(move || g(f()))),
+            Work::Produce(f) => Work::Produce(Box::new(move |emit: &mut dyn FnMut(U)| {
+                f(&mut |t| emit(g.clone()(t)))
+            })),
+        };
+        Task {
+            work,
+            thread_priority: self.thread_priority,
+            core: self.core,
+            priority: self.priority,
+        }
+    }
+
+    /// Keeps the settings, but pairs each result with the time the closure
+    /// took to produce it, measured on the thread running it.
+    pub(crate) fn timed(self) -> Task<'a, (T, Duration)>
+    where
+        T: 'a
+    {
+        let work = match self.work {
+            Work::Call(f) => Work::Call(Box::new(move || {
+                let start = Instant::now();
+                let t = f();
+                (t, start.elapsed())
+            })),
+            Work::Produce(f) => Work::Produce(Box::new(move |emit: &mut dyn FnMut((T, Duration))| {
+                let start = Instant::now();
+                f(&mut |t| emit((t, start.elapsed())))
+            })),
+        };
+        Task {
+            work,
+            thread_priority: self.thread_priority,
+            core: self.core,
+            priority: self.priority,
+        }
+    }
+}
+
+impl<T> Work<'_, T> {
+    /// Runs the closure under `policy`, catching its panic.
+    fn call(self, policy: PanicPolicy) -> Output<T> {
+        match self {
+            Work::Call(f) => {
+                Output::One(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| policy.call(f))))
+            }
+            Work::Produce(f) => {
+                let mut values = Vec::new();
+                let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    policy.call(|| f(&mut |t| values.push(t)))
+                }));
+                Output::Many(values, res.err())
+            }
+        }
+    }
+}
+
+/// A callback for panicking tasks, given the index and payload of each
+pub(crate) type PanicHook<'a> = Arc<dyn Fn(usize, &(dyn Any + Send)) + Send + Sync + 'a>;
+
+/// A middleware around each task, given its index and a way to run it
+pub(crate) type WrapFn<'a> = Arc<dyn Fn(usize, &mut dyn FnMut() -> bool) + Send + Sync + 'a>;
+
+/// A progress callback, given the tasks done and in total
+pub(crate) type ProgressFn<'a> = Arc<dyn Fn(usize, usize) + Send + Sync + 'a>;
+
+/// The counts of tasks done and in total of one run
+#[derive(Clone)]
+pub(crate) struct Progress {
+    done: sync::Arc<AtomicUsize>,
+    total: sync::Arc<AtomicUsize>,
+}
+
+impl Progress {
+    pub(crate) fn new(total: usize) -> Progress {
+        Progress {
+            done: sync::Arc::new(AtomicUsize::new(0)),
+            total: sync::Arc::new(AtomicUsize::new(total)),
+        }
+    }
+
+    /// Counts one more task in the run.
+    pub(crate) fn add(&self) {
+        self.total.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts one more task as done, returning the tasks done and in total.
+    pub(crate) fn tick(&self) -> (usize, usize) {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        (done, self.total.load(Ordering::SeqCst))
+    }
+}
+
+/// Runs the tasks of one run of a batch, on whichever thread they land
+#[derive(Clone)]
+pub(crate) struct Runner<'a> {
+    policy: PanicPolicy,
+    cancel: Option<Cancel>,
+    /// The progress callback, with its counts
+    progress: Option<(ProgressFn<'a>, Progress)>,
+    wrap: Option<WrapFn<'a>>,
+    panic_hook: Option<PanicHook<'a>>,
+    attribute_panics: bool,
+    fail_fast: bool,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl<'a> Runner<'a> {
+    /// Starts a run of `total` tasks with the settings of `config`.
+    pub(crate) fn new(config: &Config<'a>, total: usize) -> Runner<'a> {
+        Runner {
+            policy: config.panic_policy,
+            // Failing fast needs a flag to stop the batch with
+            cancel: match &config.cancel {
+                None if config.fail_fast => Some(Cancel::default()),
+                cancel => cancel.clone(),
+            },
+            progress: config.progress.clone().map(|f| (f, Progress::new(total))),
+            wrap: config.wrap.clone(),
+            panic_hook: config.panic_hook.clone(),
+            attribute_panics: config.attribute_panics,
+            fail_fast: config.fail_fast,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+        }
+    }
+
+    /// Counts one more task in the run, added while it is running.
+    pub(crate) fn add_task(&self) {
+        if let Some((_, progress)) = &self.progress {
+            progress.add();
+        }
+    }
+
+    /// Runs the task at `index`, catching its panic.
+    ///
+    /// With the `tracing` feature the task runs in a span under the span that
+    /// was current when the run started. With the `metrics` feature it counts
+    /// towards `easy_parallel_closures_total`, `easy_parallel_panics_total` and
+    /// the `easy_parallel_closure_seconds` histogram.
+    pub(crate) fn run<T>(&self, index: usize, work: Work<'a, T>) -> Output<T> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(parent: &self.span, "closure", index).entered();
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+
+        let mut output = match &self.cancel {
+            None => self.call(index, work),
+            Some(cancel) if cancel.is_cancelled() => Output::Skipped,
+            Some(cancel) => cancel.enter(|| self.call(index, work)),
+        };
+
+        #[cfg(feature = "metrics")]
+        if !matches!(output, Output::Skipped) {
+            metrics::counter!("easy_parallel_closures_total").increment(1);
+            metrics::histogram!("easy_parallel_closure_seconds").record(start.elapsed());
+            if output.is_panic() {
+                metrics::counter!("easy_parallel_panics_total").increment(1);
+            }
+        }
+
+        if let Some((f, progress)) = &self.progress {
+            let (done, total) = progress.tick();
+            // A panicking callback counts as a panic of the task
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                self.policy.call(|| f(done, total))
+            }));
+            if let Err(err) = res {
+                output = Output::One(Err(err));
+            }
+        }
+
+        if let (Some(hook), Some(payload)) = (&self.panic_hook, output.panic()) {
+            // The task already panicked, so a panicking hook changes nothing
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| hook(index, payload)));
+        }
+        if self.fail_fast && output.is_panic() {
+            if let Some(cancel) = &self.cancel {
+                cancel.cancel();
+            }
+        }
+        if self.attribute_panics {
+            output = output.map_panic(|payload| Box::new(ClosurePanic { index, payload }));
+        }
+        output
+    }
+
+    /// Runs the task at `index` under the middleware, if any.
+    fn call<T>(&self, index: usize, work: Work<'a, T>) -> Output<T> {
+        let wrap = match &self.wrap {
+            None => return work.call(self.policy),
+            Some(wrap) => wrap,
+        };
+        let mut work = Some(work);
+        let mut output = None;
+        let mut run = || {
+            if let Some(work) = work.take() {
+                output = Some(work.call(self.policy));
+            }
+            output.as_ref().is_some_and(Output::is_panic)
+        };
+        // A panic in the middleware counts as a panic of the task
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.policy.call(|| wrap(index, &mut run))
+        }));
+        match (res, output) {
+            (Err(err), _) => Output::One(Err(err)),
+            (Ok(()), Some(output)) => output,
+            (Ok(()), None) => Output::One(Err(Box::new("wrap() middleware did not run the closure"))),
+        }
+    }
+}
+
+impl<T> Output<T> {
+    /// Replaces the panic payload, if there is one, with `f` of it.
+    fn map_panic<F>(self, f: F) -> Output<T>
+    where
+        F: FnOnce(Box<dyn Any + Send>) -> Box<dyn Any + Send>
+    {
+        match self {
+            Output::One(Err(err)) => Output::One(Err(f(err))),
+            Output::Many(values, Some(err)) => Output::Many(values, Some(f(err))),
+            output => output,
+        }
+    }
+
+    /// Returns whether the task panicked.
+    fn is_panic(&self) -> bool {
+        self.panic().is_some()
+    }
+
+    /// Returns the panic payload of the task, if it panicked.
+    fn panic(&self) -> Option<&(dyn Any + Send)> {
+        match self {
+            Output::One(Err(err)) | Output::Many(_, Some(err)) => Some(&**err),
+            _ => None,
+        }
+    }
+
+    /// Flattens the outputs of a batch into one result per value, in order.
+    pub(crate) fn flatten(outputs: Vec<Output<T>>) -> Vec<std::thread::Result<T>> {
+        let mut results = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            match output {
+                Output::One(res) => results.push(res),
+                Output::Many(values, err) => {
+                    results.extend(values.into_iter().map(Ok));
+                    results.extend(err.map(Err));
+                }
+                Output::Skipped => {}
+            }
+        }
+        results
+    }
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use super::{Output, Progress, Runner, Work};
+    use crate::Config;
+    use loom::thread;
+
+    #[test]
+    fn progress_never_counts_past_total() {
+        loom::model(|| {
+            let progress = Progress::new(2);
+            let added = {
+                let progress = progress.clone();
+                thread::spawn(move || {
+                    progress.add();
+                    [progress.tick(), progress.tick()]
+                })
+            };
+            let last = progress.tick();
+            let mut ticks = added.join().unwrap().to_vec();
+            ticks.push(last);
+            for &(done, total) in &ticks {
+                assert!(done <= total);
+            }
+            ticks.sort();
+            assert_eq!(ticks.iter().map(|t| t.0).collect::<Vec<_>>(), [1, 2, 3]);
+            assert_eq!(ticks[2], (3, 3));
+        });
+    }
+
+    #[test]
+    fn fail_fast_skips_tasks_started_after_a_panic() {
+        loom::model(|| {
+            let config = Config {
+                fail_fast: true,
+                ..Config::default()
+            };
+            let runner = Runner::new(&config, 3);
+            let panicked = {
+                let runner = runner.clone();
+                thread::spawn(move || {
+                    let work = Work::Call(Box::new(|| -> i32 { panic!("bad task") }));
+                    runner.run(0, work).is_panic()
+                })
+            };
+            let racing = runner.run(1, Work::Call(Box::new(|| 1)));
+            assert!(panicked.join().unwrap());
+            // Tasks racing the panic may run or not, later ones never do
+            assert!(matches!(racing, Output::One(Ok(1)) | Output::Skipped));
+            assert!(matches!(runner.run(2, Work::Call(Box::new(|| 2))), Output::Skipped));
+        });
+    }
+}